@@ -0,0 +1,276 @@
+use bytemuck::bytes_of;
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+use wgpu::{Device, Queue};
+
+use crate::{binding::{Descriptor, StorageVec, UniformBinding}, model::ToRaw, VertexTrait};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translation: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0), scale: Vector3::new(1.0, 1.0, 1.0) }
+    }
+}
+
+impl Transform {
+    // glTF order, the rotation is [x, y, z, w]
+    pub fn from_decomposed((translation, rotation, scale): ([f32; 3], [f32; 4], [f32; 3])) -> Self {
+        Self {
+            translation: translation.into(),
+            rotation: Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+            scale: scale.into(),
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation) * Matrix4::from(self.rotation) * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // weight 0 is self, 1 is other
+    pub fn blend(&self, other: &Transform, weight: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(other.translation, weight),
+            rotation: slerp(self.rotation, other.rotation, weight),
+            scale: self.scale.lerp(other.scale, weight),
+        }
+    }
+}
+
+// Blends two poses node by node, they have to come from the same model
+pub fn blend_poses(a: &[Transform], b: &[Transform], weight: f32) -> Vec<Transform> {
+    a.iter().zip(b).map(|(a, b)| a.blend(b, weight)).collect()
+}
+
+// cgmath's slerp doesn't take the shortest path
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, t).normalize()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    CubicSpline,
+}
+
+pub struct Channel {
+    // index of the node the channel animates
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    // rotations are [x, y, z, w], translations and scales leave w at 0.
+    // With CubicSpline every keyframe has 3 values: in tangent, value, out tangent
+    pub values: Vec<[f32; 4]>,
+}
+
+impl Channel {
+    fn value(&self, keyframe: usize) -> [f32; 4] {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[keyframe * 3 + 1],
+            _ => self.values[keyframe],
+        }
+    }
+
+    pub fn sample(&self, time: f32) -> [f32; 4] {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return self.value(0);
+        }
+        if time >= self.times[last] {
+            return self.value(last);
+        }
+        let next = self.times.partition_point(|keyframe_time| *keyframe_time <= time);
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / delta;
+        match self.interpolation {
+            Interpolation::Step => self.value(previous),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let (a, b) = (self.value(previous), self.value(next));
+                let rotation = slerp(Quaternion::new(a[3], a[0], a[1], a[2]), Quaternion::new(b[3], b[0], b[1], b[2]), t);
+                [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]
+            }
+            Interpolation::Linear => {
+                let (a, b) = (self.value(previous), self.value(next));
+                [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+            }
+            Interpolation::CubicSpline => {
+                let p0 = self.values[previous * 3 + 1];
+                let m0 = self.values[previous * 3 + 2];
+                let p1 = self.values[next * 3 + 1];
+                let m1 = self.values[next * 3];
+                let (t2, t3) = (t * t, t * t * t);
+                let value = [0, 1, 2, 3].map(|i| {
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * p0[i] + (t3 - 2.0 * t2 + t) * delta * m0[i] + (-2.0 * t3 + 3.0 * t2) * p1[i] + (t3 - t2) * delta * m1[i]
+                });
+                if self.property == Property::Rotation {
+                    let rotation = Quaternion::new(value[3], value[0], value[1], value[2]).normalize();
+                    [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]
+                } else {
+                    value
+                }
+            }
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    // Overwrites the animated properties of `pose`, nodes without channels keep their value
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            if channel.times.is_empty() {
+                continue;
+            }
+            let Some(transform) = pose.get_mut(channel.node) else { continue; };
+            let value = channel.sample(time);
+            match channel.property {
+                Property::Translation => transform.translation = Vector3::new(value[0], value[1], value[2]),
+                Property::Rotation => transform.rotation = Quaternion::new(value[3], value[0], value[1], value[2]),
+                Property::Scale => transform.scale = Vector3::new(value[0], value[1], value[2]),
+            }
+        }
+    }
+
+    pub fn sample_looped(&self, time: f32, pose: &mut [Transform]) {
+        let time = if self.duration > 0.0 { time.rem_euclid(self.duration) } else { 0.0 };
+        self.sample(time, pose);
+    }
+
+    // Samples both clips on top of `rest` and blends them, weight 0 is entirely `self`
+    pub fn sample_blended(&self, time: f32, other: &AnimationClip, other_time: f32, weight: f32, rest: &[Transform]) -> Vec<Transform> {
+        let mut a = rest.to_vec();
+        let mut b = rest.to_vec();
+        self.sample_looped(time, &mut a);
+        other.sample_looped(other_time, &mut b);
+        blend_poses(&a, &b, weight)
+    }
+}
+
+pub struct Skeleton {
+    pub name: Option<String>,
+    // node index of every joint, the joint indices of `SkinnedVertex` index into this
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Skeleton {
+    // `world_transforms` are the node transforms relative to the model, like `GltfModel::world_transforms`
+    pub fn joint_matrices(&self, world_transforms: &[Matrix4<f32>]) -> Vec<[[f32; 4]; 4]> {
+        self.joints.iter().zip(&self.inverse_bind_matrices).map(|(node, inverse_bind)| (world_transforms[*node] * inverse_bind).into()).collect()
+    }
+
+    // shows up in shaders as array<mat4x4f>
+    pub fn create_binding(&self, device: &Device) -> UniformBinding<StorageVec<[[f32; 4]; 4]>> {
+        UniformBinding::new(device, "Joint Matrices", StorageVec(vec![Matrix4::identity().into(); self.joints.len()]), None)
+    }
+
+    pub fn update_binding(&self, binding: &mut UniformBinding<StorageVec<[[f32; 4]; 4]>>, world_transforms: &[Matrix4<f32>], queue: &Queue) {
+        binding.set_data(queue, StorageVec(self.joint_matrices(world_transforms)));
+    }
+}
+
+// joints and weights are at locations 14 and 15, past the ones `Instance` uses, so skinned meshes can be instanced
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub tex_coords1: [f32; 2],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl VertexTrait for SkinnedVertex {
+    fn pos(&self) -> cgmath::Vector3<f32> {
+        self.position.into()
+    }
+}
+
+impl Descriptor for SkinnedVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+impl ToRaw for SkinnedVertex {
+    fn to_raw(&self) -> Vec<u8> {
+        bytes_of(self).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binding::Descriptor, instance::NormalInstance};
+
+    use super::SkinnedVertex;
+
+    #[test]
+    fn skinned_vertex_locations_leave_room_for_instances() {
+        let vertex = SkinnedVertex::desc().attributes.iter().map(|attribute| attribute.shader_location).collect::<Vec<_>>();
+        let instance = NormalInstance::<[f32; 4]>::desc().attributes.iter().map(|attribute| attribute.shader_location).collect::<Vec<_>>();
+        assert!(vertex.iter().all(|location| !instance.contains(location)));
+        assert!(vertex.iter().chain(&instance).all(|location| *location < 16));
+    }
+}
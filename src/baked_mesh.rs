@@ -0,0 +1,352 @@
+use std::{borrow::Cow, io::{BufReader, Cursor}, path::Path};
+
+use anyhow::{anyhow, bail};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{binding::Descriptor, mesh::{MaterialMap, MaterialUniform, ModelVertex, TangentModelVertex}, mesh_processing::{generate_normals, generate_tangents, optimize_mesh, MeshProcessingOptions, OptimizationReport}, model::calculate_bounding_box, VertexTrait};
+
+pub const BAKED_MESH_MAGIC: [u8; 4] = *b"BMSH";
+pub const BAKED_MESH_VERSION: u32 = 1;
+
+// `BakedAttribute::format` is an index into this list
+pub const BAKED_VERTEX_FORMATS: [wgpu::VertexFormat; 12] = [
+    wgpu::VertexFormat::Float32,
+    wgpu::VertexFormat::Float32x2,
+    wgpu::VertexFormat::Float32x3,
+    wgpu::VertexFormat::Float32x4,
+    wgpu::VertexFormat::Uint32,
+    wgpu::VertexFormat::Uint32x2,
+    wgpu::VertexFormat::Uint32x3,
+    wgpu::VertexFormat::Uint32x4,
+    wgpu::VertexFormat::Sint32,
+    wgpu::VertexFormat::Sint32x2,
+    wgpu::VertexFormat::Sint32x3,
+    wgpu::VertexFormat::Sint32x4,
+];
+
+// The file is the header followed by the attributes, submeshes, materials, the string table padded to 4 bytes,
+// the vertex data and the index data. Everything is little endian and 4 byte aligned so the tables can be cast in place
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BakedHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub vertex_stride: u32,
+    pub attribute_count: u32,
+    // 0 for u16, 1 for u32
+    pub index_format: u32,
+    pub submesh_count: u32,
+    pub material_count: u32,
+    pub string_bytes: u32,
+    pub vertex_bytes: u32,
+    pub index_bytes: u32,
+    // half extents of every submesh together, like `AABB`
+    pub bounding_box: [f32; 3],
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BakedAttribute {
+    pub format: u32,
+    pub offset: u32,
+    pub shader_location: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BakedSubmesh {
+    // in bytes from the start of the vertex data
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    // in bytes from the start of the index data, the indices start at 0 for every submesh
+    pub index_offset: u32,
+    pub index_count: u32,
+    pub material: u32,
+    pub bounding_box: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BakedMaterial {
+    pub values: MaterialUniform,
+    // [offset, length] in the string table
+    pub name: [u32; 2],
+    // the resource path of every `MaterialMap` in `MaterialMap::ALL` order, length 0 if there is none
+    pub textures: [[u32; 2]; 9],
+}
+
+// Borrows the tables and data from the file when it's aligned, so loading doesn't copy the vertices before uploading them
+pub struct BakedMesh<'a> {
+    pub header: BakedHeader,
+    pub attributes: Cow<'a, [BakedAttribute]>,
+    pub submeshes: Cow<'a, [BakedSubmesh]>,
+    pub materials: Cow<'a, [BakedMaterial]>,
+    pub strings: Cow<'a, [u8]>,
+    pub vertex_data: Cow<'a, [u8]>,
+    pub index_data: Cow<'a, [u8]>,
+}
+
+impl<'a> BakedMesh<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        let header_size = std::mem::size_of::<BakedHeader>();
+        if bytes.len() < header_size {
+            bail!("baked mesh is too short for its header");
+        }
+        let header: BakedHeader = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        if header.magic != BAKED_MESH_MAGIC {
+            bail!("not a baked mesh");
+        }
+        if header.version != BAKED_MESH_VERSION {
+            bail!("baked mesh version {} isn't supported, expected {BAKED_MESH_VERSION}", header.version);
+        }
+        let mut offset = header_size;
+        let mut section = |length: usize| -> anyhow::Result<&'a [u8]> {
+            let data = bytes.get(offset..offset + length).ok_or(anyhow!("baked mesh is truncated"))?;
+            offset += length.next_multiple_of(4);
+            Ok(data)
+        };
+        let attributes = cast_section(section(header.attribute_count as usize * std::mem::size_of::<BakedAttribute>())?);
+        let submeshes = cast_section(section(header.submesh_count as usize * std::mem::size_of::<BakedSubmesh>())?);
+        let materials = cast_section(section(header.material_count as usize * std::mem::size_of::<BakedMaterial>())?);
+        let strings = Cow::Borrowed(section(header.string_bytes as usize)?);
+        let vertex_data = Cow::Borrowed(section(header.vertex_bytes as usize)?);
+        let index_data = Cow::Borrowed(section(header.index_bytes as usize)?);
+        Ok(Self { header, attributes, submeshes, materials, strings, vertex_data, index_data })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bytemuck::bytes_of(&self.header).to_vec();
+        for section in [
+            bytemuck::cast_slice(&self.attributes),
+            bytemuck::cast_slice(&self.submeshes),
+            bytemuck::cast_slice(&self.materials),
+            &self.strings[..],
+            &self.vertex_data[..],
+            &self.index_data[..],
+        ] {
+            bytes.extend_from_slice(section);
+            bytes.resize(bytes.len().next_multiple_of(4), 0);
+        }
+        bytes
+    }
+
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        if self.header.index_format == 0 { wgpu::IndexFormat::Uint16 } else { wgpu::IndexFormat::Uint32 }
+    }
+
+    pub fn vertex_attributes(&self) -> anyhow::Result<Vec<wgpu::VertexAttribute>> {
+        self.attributes.iter().map(|attribute| Ok(wgpu::VertexAttribute {
+            format: *BAKED_VERTEX_FORMATS.get(attribute.format as usize).ok_or(anyhow!("unknown baked vertex format {}", attribute.format))?,
+            offset: attribute.offset as wgpu::BufferAddress,
+            shader_location: attribute.shader_location,
+        })).collect()
+    }
+
+    // Checks that the baked vertices can be drawn with the buffer layout of `V`
+    pub fn matches_vertex<V: Descriptor>(&self) -> bool {
+        let layout = V::desc();
+        layout.array_stride == self.header.vertex_stride as wgpu::BufferAddress && self.vertex_attributes().is_ok_and(|attributes| attributes == layout.attributes)
+    }
+
+    pub fn string(&self, [offset, length]: [u32; 2]) -> &str {
+        offset.checked_add(length).and_then(|end| self.strings.get(offset as usize..end as usize)).and_then(|bytes| std::str::from_utf8(bytes).ok()).unwrap_or("")
+    }
+
+    pub fn material_name(&self, material: &BakedMaterial) -> &str {
+        self.string(material.name)
+    }
+
+    pub fn texture_path(&self, material: &BakedMaterial, map: MaterialMap) -> Option<&str> {
+        let path = material.textures[MaterialMap::ALL.iter().position(|it| *it == map)?];
+        (path[1] > 0).then(|| self.string(path))
+    }
+
+    pub fn submesh_vertices(&self, submesh: &BakedSubmesh) -> anyhow::Result<&[u8]> {
+        let start = submesh.vertex_offset as usize;
+        self.vertex_data.get(start..start + submesh.vertex_count as usize * self.header.vertex_stride as usize).ok_or(anyhow!("baked submesh vertices are out of bounds"))
+    }
+
+    pub fn submesh_indices(&self, submesh: &BakedSubmesh) -> anyhow::Result<&[u8]> {
+        let start = submesh.index_offset as usize;
+        let size = if self.index_format() == wgpu::IndexFormat::Uint16 { 2 } else { 4 };
+        self.index_data.get(start..start + submesh.index_count as usize * size).ok_or(anyhow!("baked submesh indices are out of bounds"))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(std::fs::write(path, self.to_bytes())?)
+    }
+}
+
+impl BakedMesh<'static> {
+    // Parses and processes an OBJ the same way `MeshModel::load_model_with` does, without touching the GPU so it also works in build scripts.
+    // `load_file` gets the resource path of the OBJ and its MTL files, texture paths are stored as resource paths relative to the OBJ
+    pub fn from_obj(source_path: &Path, options: &MeshProcessingOptions, load_file: impl Fn(&Path) -> Option<Vec<u8>>) -> anyhow::Result<(Self, Vec<OptimizationReport>)> {
+        let parent = source_path.parent().unwrap_or(Path::new(""));
+        let obj_bytes = load_file(source_path).ok_or(anyhow!("tried to load resource at {}", source_path.display()))?;
+        let (models, obj_materials) = tobj::load_obj_buf(
+            &mut BufReader::new(Cursor::new(obj_bytes)),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |p| {
+                let bytes = load_file(&parent.join(p)).ok_or(tobj::LoadError::OpenFileFailed)?;
+                tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(bytes)))
+            },
+        )?;
+
+        let mut baker = Baker::default();
+        for m in obj_materials? {
+            let mut textures = [[0; 2]; 9];
+            for (map, file) in [
+                (MaterialMap::Diffuse, &m.diffuse_texture),
+                (MaterialMap::Ambient, &m.ambient_texture),
+                (MaterialMap::Specular, &m.specular_texture),
+                (MaterialMap::Shininess, &m.shininess_texture),
+                (MaterialMap::Normal, &m.normal_texture),
+                (MaterialMap::Dissolve, &m.dissolve_texture),
+            ] {
+                let Some(file) = file else { continue; };
+                // options like `-bm 1.0` come before the file name
+                let file = if file.starts_with('-') { file.split_whitespace().last().unwrap_or(file) } else { file };
+                let path = parent.join(file).as_os_str().to_str().unwrap().replace("\\", "/");
+                textures[MaterialMap::ALL.iter().position(|it| *it == map).unwrap()] = baker.string(&path);
+            }
+            let name = baker.string(&m.name);
+            baker.materials.push(BakedMaterial { values: MaterialUniform::from(&m), name, textures });
+        }
+        // meshes without a material get a default one at the end of the list
+        let default_material = baker.materials.len();
+        if models.iter().any(|m| m.mesh.material_id.is_none_or(|id| id >= default_material)) {
+            let name = baker.string("Default");
+            baker.materials.push(BakedMaterial { values: MaterialUniform::default(), name, textures: [[0; 2]; 9] });
+        }
+        for m in models {
+            let mut positions = m.mesh.positions.chunks_exact(3).map(|it| [it[0], it[1], it[2]]).collect::<Vec<_>>();
+            let mut tex_coords = (0..positions.len())
+                .map(|i| [*m.mesh.texcoords.get(i * 2).unwrap_or(&0.0), 1.0 - *m.mesh.texcoords.get(i * 2 + 1).unwrap_or(&0.0)])
+                .collect::<Vec<_>>();
+            let mut indices = m.mesh.indices;
+            let normals = if m.mesh.normals.len() < positions.len() * 3 || options.recompute_normals {
+                let generated = generate_normals(&positions, &indices, options.normals);
+                positions = generated.remap.iter().map(|i| positions[*i as usize]).collect();
+                tex_coords = generated.remap.iter().map(|i| tex_coords[*i as usize]).collect();
+                indices = generated.indices;
+                generated.normals
+            } else {
+                m.mesh.normals.chunks_exact(3).map(|it| [it[0], it[1], it[2]]).collect()
+            };
+            let material = m.mesh.material_id.filter(|id| *id < default_material).unwrap_or(default_material) as u32;
+            if options.tangents {
+                let tangents = generate_tangents(&positions, &normals, &tex_coords, &indices);
+                let vertices = (0..positions.len()).map(|i| TangentModelVertex { position: positions[i], tex_coords: tex_coords[i], normal: normals[i], tangent: tangents[i] }).collect();
+                baker.submesh(vertices, indices, material, options.optimize);
+            } else {
+                let vertices = (0..positions.len()).map(|i| ModelVertex { position: positions[i], tex_coords: tex_coords[i], normal: normals[i] }).collect();
+                baker.submesh(vertices, indices, material, options.optimize);
+            }
+        }
+        Ok(baker.finish())
+    }
+}
+
+fn cast_section<T: Pod>(bytes: &[u8]) -> Cow<'_, [T]> {
+    match bytemuck::try_cast_slice(bytes) {
+        Ok(slice) => Cow::Borrowed(slice),
+        Err(_) => Cow::Owned(bytemuck::pod_collect_to_vec(bytes)),
+    }
+}
+
+#[derive(Default)]
+struct Baker {
+    attributes: Vec<BakedAttribute>,
+    vertex_stride: u32,
+    submeshes: Vec<BakedSubmesh>,
+    materials: Vec<BakedMaterial>,
+    strings: Vec<u8>,
+    vertex_data: Vec<u8>,
+    // kept as u32 until `finish` knows the index format
+    indices: Vec<Vec<u32>>,
+    wide_indices: bool,
+    reports: Vec<OptimizationReport>,
+}
+
+impl Baker {
+    fn string(&mut self, string: &str) -> [u32; 2] {
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(string.as_bytes());
+        [offset, string.len() as u32]
+    }
+
+    fn submesh<V: Pod + Descriptor + VertexTrait>(&mut self, vertices: Vec<V>, indices: Vec<u32>, material: u32, optimize: bool) {
+        let layout = V::desc();
+        self.vertex_stride = layout.array_stride as u32;
+        self.attributes = layout.attributes.iter().map(|attribute| BakedAttribute {
+            format: BAKED_VERTEX_FORMATS.iter().position(|format| *format == attribute.format).expect("vertex format can't be baked") as u32,
+            offset: attribute.offset as u32,
+            shader_location: attribute.shader_location,
+        }).collect();
+        // every submesh has to use the same index format, so u16 only works if they all fit
+        let (vertices, indices) = if optimize {
+            let (vertices, indices, report) = optimize_mesh(vertices, indices, wgpu::IndexFormat::Uint32);
+            self.reports.push(report);
+            (vertices, indices)
+        } else {
+            // unoptimized OBJs keep the u32 indices `MeshModel::load_model` always used
+            self.wide_indices = true;
+            (vertices, indices)
+        };
+        self.wide_indices |= vertices.len() > u16::MAX as usize + 1;
+        let bounding_box = calculate_bounding_box(&vertices).dimensions;
+        self.submeshes.push(BakedSubmesh {
+            vertex_offset: self.vertex_data.len() as u32,
+            vertex_count: vertices.len() as u32,
+            index_offset: 0,
+            index_count: indices.len() as u32,
+            material,
+            bounding_box,
+        });
+        self.vertex_data.extend_from_slice(bytemuck::cast_slice(&vertices));
+        self.indices.push(indices);
+    }
+
+    fn finish(mut self) -> (BakedMesh<'static>, Vec<OptimizationReport>) {
+        let mut index_data = vec![];
+        for (submesh, indices) in self.submeshes.iter_mut().zip(&self.indices) {
+            submesh.index_offset = index_data.len() as u32;
+            if self.wide_indices {
+                index_data.extend_from_slice(bytemuck::cast_slice(indices));
+            } else {
+                index_data.extend_from_slice(bytemuck::cast_slice(&indices.iter().map(|index| *index as u16).collect::<Vec<_>>()));
+                // keeps the next submesh 4 byte aligned
+                index_data.resize(index_data.len().next_multiple_of(4), 0);
+            }
+        }
+        let bounding_box = self.submeshes.iter().fold([0.0_f32; 3], |extent, submesh| [0, 1, 2].map(|i| extent[i].max(submesh.bounding_box[i])));
+        let header = BakedHeader {
+            magic: BAKED_MESH_MAGIC,
+            version: BAKED_MESH_VERSION,
+            vertex_stride: self.vertex_stride,
+            attribute_count: self.attributes.len() as u32,
+            index_format: self.wide_indices as u32,
+            submesh_count: self.submeshes.len() as u32,
+            material_count: self.materials.len() as u32,
+            string_bytes: self.strings.len() as u32,
+            vertex_bytes: self.vertex_data.len() as u32,
+            index_bytes: index_data.len() as u32,
+            bounding_box,
+            padding: 0,
+        };
+        (BakedMesh {
+            header,
+            attributes: Cow::Owned(self.attributes),
+            submeshes: Cow::Owned(self.submeshes),
+            materials: Cow::Owned(self.materials),
+            strings: Cow::Owned(self.strings),
+            vertex_data: Cow::Owned(self.vertex_data),
+            index_data: Cow::Owned(index_data),
+        }, self.reports)
+    }
+}
@@ -1,49 +1,143 @@
-use crate::{binding::Descriptor, culling::culled, instance::Instance, model::{calculate_bounding_box, Model, Render, ToRaw}, VertexTrait};
-use bytemuck::{bytes_of, NoUninit};
-use cgmath::{Matrix4, Quaternion, Vector3};
-use wgpu::Device;
+use crate::{binding::{create_layout, Binding, Descriptor, Uniform, UniformBinding, WgslType}, culling::{culled, CullingCompute, AABB}, instance::Instance, model::{calculate_bounding_box, Model, Render, ToRaw}, shader::{Shader, ShaderConfig}, surface_context::SurfaceCtx, VertexTrait};
+use bytemuck::{bytes_of, NoUninit, Pod, Zeroable};
+use cgmath::{InnerSpace, Quaternion, Vector3};
+use wgpu::{Device, Queue, TextureFormat};
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BillboardMode {
+    // uses the rotation of the instances
+    Fixed,
+    // faces the camera from any side
+    Spherical,
+    // only turns around the axis to face the camera
+    Cylindrical { axis: Vector3<f32> },
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+pub struct BillboardSettings {
+    pub axis: [f32; 3],
+    // 0 = fixed, 1 = spherical, 2 = cylindrical
+    pub mode: u32,
+}
+
+impl From<BillboardMode> for BillboardSettings {
+    fn from(mode: BillboardMode) -> Self {
+        match mode {
+            BillboardMode::Fixed => Self { axis: [0.0, 1.0, 0.0], mode: 0 },
+            BillboardMode::Spherical => Self { axis: [0.0, 1.0, 0.0], mode: 1 },
+            BillboardMode::Cylindrical { axis } => Self { axis: axis.into(), mode: 2 },
+        }
+    }
+}
+
+impl WgslType for BillboardSettings {
+    fn wgsl_name() -> String {
+        "BillboardSettings".into()
+    }
+}
+
+// A textured quad drawn once per instance. In the facing modes the built-in shader turns it towards the camera
+// every frame, so the rotation doesn't have to be updated from the CPU. Shaders get the settings for the mode at
+// group 1, see `Billboard::shader`
 pub struct Billboard {
     model: Model,
-    position: Vector3<f32>,
-    rotation: Quaternion<f32>,
+    instances: Vec<Instance>,
+    mode: BillboardMode,
+    // the quad bounds in the fixed mode
+    quad_bounds: AABB,
+    settings: UniformBinding<BillboardSettings>,
 }
 
 impl Billboard {
     pub fn new(width: f32, height: f32, size: f32, position: Vector3<f32>, rotation: Quaternion<f32>, device: &Device) -> Self {
+        Self::instanced(width, height, size, vec![Instance { position, rotation, ..Default::default() }], BillboardMode::Fixed, device)
+    }
+
+    // Many billboards sharing one quad, the facing modes only use the position and scale of the instances
+    pub fn instanced(width: f32, height: f32, size: f32, instances: Vec<Instance>, mode: BillboardMode, device: &Device) -> Self {
         let vertices = vec![
             Vertex { position: [size*-width/2.0, size*-height/2.0, 0.0], tex_pos: [0.0, 1.0], normal: [0.0, 0.0, 0.0] },
             Vertex { position: [size*-width/2.0, size*height/2.0, 0.0], tex_pos: [0.0, 0.0], normal: [0.0, 0.0, 0.0] },
             Vertex { position: [size*width/2.0, size*-height/2.0, 0.0], tex_pos: [1.0, 1.0], normal: [0.0, 0.0, 0.0] },
             Vertex { position: [size*width/2.0, size*height/2.0, 0.0], tex_pos: [1.0, 0.0], normal: [0.0, 0.0, 0.0] },
         ];
-        let bounding_box = calculate_bounding_box(&vertices);
-        let model = Model::new_instances(vertices, &[0_u16, 1, 2, 2, 1, 3], vec![Instance {position, rotation}], bounding_box, device);
-        Self {
+        let quad_bounds = calculate_bounding_box(&vertices);
+        let model = Model::new_instances(vertices, &[0_u16, 1, 2, 2, 1, 3], instances.clone(), quad_bounds, device);
+        let mut billboard = Self {
             model,
-            position,
-            rotation,
-        }
+            instances,
+            mode,
+            quad_bounds,
+            settings: UniformBinding::new(device, "Billboard Settings", mode.into(), None),
+        };
+        billboard.update_bounds();
+        billboard
+    }
+
+    // The built-in billboard shader, with the camera at group 0 and the texture at group 2
+    pub fn shader(device: &Device, formats: Vec<TextureFormat>, camera: &dyn Uniform, texture: &dyn Uniform, config: ShaderConfig) -> Shader<'static> {
+        let settings_layout = create_layout::<BillboardSettings>((), device);
+        Shader::new(
+            "buildins/billboard.wgsl",
+            device,
+            formats,
+            vec![camera.layout(), &settings_layout, texture.layout()],
+            vec![camera.shader_type(), &BillboardSettings::shader_type(()), texture.shader_type()],
+            vec![Vertex::desc(), Instance::<()>::desc()],
+            config,
+        )
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>, device: &Device, queue: &Queue) {
+        let rotation = self.instances.first().map(|instance| instance.rotation).unwrap_or(Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        self.set_both(position, rotation, device, queue);
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion<f32>, device: &Device, queue: &Queue) {
+        let position = self.instances.first().map(|instance| instance.position).unwrap_or(Vector3::new(0.0, 0.0, 0.0));
+        self.set_both(position, rotation, device, queue);
+    }
+
+    pub fn set_both(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, device: &Device, queue: &Queue) {
+        self.set_instances(vec![Instance { position, rotation, ..Default::default() }], device, queue);
+    }
+
+    pub fn set_instances(&mut self, instances: Vec<Instance>, device: &Device, queue: &Queue) {
+        self.instances = instances;
+        self.model.update_instances(self.instances.clone(), device, queue);
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn mode(&self) -> BillboardMode {
+        self.mode
     }
 
-    pub fn set_position(&mut self, position: Vector3<f32>, device: &Device) {
-        self.position = position;
-        self.create_instance(device);
+    pub fn set_mode(&mut self, mode: BillboardMode, queue: &Queue) {
+        self.mode = mode;
+        self.settings.set_data(queue, mode.into());
+        self.update_bounds();
     }
 
-    pub fn set_rotation(&mut self, rotation: Quaternion<f32>, device: &Device) {
-        self.rotation = rotation;
-        self.create_instance(device);
+    // bind it at group 1 for the built-in shader
+    pub fn settings(&self) -> &UniformBinding<BillboardSettings> {
+        &self.settings
     }
 
-    pub fn set_both(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, device: &Device) {
-        self.position = position;
-        self.rotation = rotation;
-        self.create_instance(device);
+    pub fn model(&self) -> &Model {
+        &self.model
     }
 
-    fn create_instance(&mut self, device: &Device) {
-        self.model.update_instances(vec![Instance {position: self.position, rotation: self.rotation}], device);
+    // A quad turning to face the camera can point any way, so the facing modes cull against a box that holds it in
+    // every orientation
+    fn update_bounds(&mut self) {
+        self.model.bounding_box = match self.mode {
+            BillboardMode::Fixed => self.quad_bounds,
+            _ => AABB { dimensions: [Vector3::from(self.quad_bounds.dimensions).magnitude(); 3] },
+        };
     }
 }
 
@@ -55,13 +149,18 @@ impl Render for Billboard {
         self.model.render_instances(render_pass, instances, range);
     }
     fn render_culled_transformed<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instance_transform: Option<cgmath::Matrix4<f32>>, camera: &crate::camera::Camera) {
-        if !culled(&self.model, instance_transform.unwrap_or(Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)), camera) {
+        let visible = match instance_transform {
+            Some(transform) => !culled(&self.model, transform, camera),
+            None => self.instances.iter().any(|instance| !culled(&self.model, instance.model_matrix(), camera)),
+        };
+        if visible {
             self.render(render_pass);
         }
     }
 
-    fn render_culled<'a: 'b, 'b>(&'a self, _camera: &crate::binding::UniformBinding<crate::camera::Camera>, _render_pass: &mut wgpu::RenderPass<'b>, _culling: &mut crate::culling::CullingCompute, _surface_ctx: &dyn crate::surface_context::SurfaceCtx) {
-        todo!()
+    // Culls the instances on the GPU, `culling` has to be made for `Instance<()>`
+    fn render_culled<'a: 'b, 'b>(&'a self, camera: &UniformBinding<crate::camera::Camera>, render_pass: &mut wgpu::RenderPass<'b>, culling: &mut CullingCompute, surface_ctx: &dyn SurfaceCtx) {
+        self.model.render_culled(camera, render_pass, culling, surface_ctx);
     }
 }
 
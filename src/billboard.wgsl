@@ -0,0 +1,63 @@
+// The built-in shader for `Billboard`, see `Billboard::shader`
+#include "buildins/billboard_facing.wgsl"
+
+camera: $0;
+billboard: $1;
+billboard_texture: $2,0;
+billboard_sampler: $2,1;
+
+// mode 0 = fixed, 1 = spherical, 2 = cylindrical
+struct BillboardSettings {
+    axis: vec3f,
+    mode: u32,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var world_position = (model_matrix * vec4(model.position, 1.0)).xyz;
+    if billboard.mode != 0u {
+        // the facing modes replace the rotation of the instance and keep its position and scale
+        let center = model_matrix[3].xyz;
+        let corner = model.position.xy * vec2(length(model_matrix[0].xyz), length(model_matrix[1].xyz));
+        if billboard.mode == 1u {
+            world_position = billboard_spherical(center, corner, camera.position);
+        } else {
+            world_position = billboard_cylindrical(center, corner, billboard.axis, camera.position);
+        }
+    }
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4(world_position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(billboard_texture, billboard_sampler, in.tex_coords);
+}
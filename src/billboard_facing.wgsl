@@ -0,0 +1,27 @@
+// Camera facing billboards, pulled into a shader with `#include "buildins/billboard_facing.wgsl"`. `corner` is the
+// offset of a vertex from the center of the billboard along its right and up directions
+
+// turns to face the camera from any side
+fn billboard_spherical(center: vec3<f32>, corner: vec2<f32>, camera_position: vec3<f32>) -> vec3<f32> {
+    let forward = normalize(camera_position - center);
+    var right = cross(vec3(0.0, 1.0, 0.0), forward);
+    // seen from straight above or below
+    if dot(right, right) < 1e-6 {
+        right = vec3(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(forward, right);
+    return center + right * corner.x + up * corner.y;
+}
+
+// only turns around `axis`, which stays its up direction, like trees or flames
+fn billboard_cylindrical(center: vec3<f32>, corner: vec2<f32>, axis: vec3<f32>, camera_position: vec3<f32>) -> vec3<f32> {
+    let up = normalize(axis);
+    var right = cross(up, camera_position - center);
+    // seen along the axis
+    if dot(right, right) < 1e-6 {
+        right = cross(up, select(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), abs(up.x) > 0.9));
+    }
+    right = normalize(right);
+    return center + right * corner.x + up * corner.y;
+}
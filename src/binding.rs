@@ -356,6 +356,41 @@ impl <T: bytemuck::Pod + WgslType, const N: usize> Binding for DynamicOffsetUnif
     }
 }
 
+// A read only storage buffer, shows up in shaders as array<T>. Use `replace_data` if the length changes
+pub struct StorageVec<T>(pub Vec<T>);
+
+impl <T: bytemuck::Pod + WgslType> Binding for StorageVec<T> {
+    type LayoutConfig = ();
+    fn layout(_config: Self::LayoutConfig, ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: ty.unwrap_or(wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, has_dynamic_offset: false, min_binding_size: None })
+            }
+        ]
+    }
+
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        let mut bytes = bytemuck::cast_slice(&self.0).to_vec();
+        // empty storage buffers can't be bound
+        if bytes.is_empty() {
+            bytes = vec![0; size_of::<T>().max(4)];
+        }
+        vec![Resource::Simple(bytes)]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["<storage, read>".into()],
+            wgsl_types: vec![format!("array<{}>", T::wgsl_name())],
+        }
+    }
+}
+
 pub fn bind_resources<'a, B: Binding>(value: &B, device: &Device) -> BindGroup {
     let resources = value.create_resources();
     let mut buffers = HashMap::new();
@@ -0,0 +1,39 @@
+struct BloomSettings {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    filter_radius: f32,
+};
+
+previous_texture: $0,0;
+previous_sampler: $0,1;
+bloom_texture: $1,0;
+bloom_sampler: $1,1;
+settings: $2;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(previous_texture, previous_sampler, in.tex_coords);
+    let bloom = textureSample(bloom_texture, bloom_sampler, in.tex_coords).rgb;
+    return vec4(color.rgb + bloom * settings.intensity, color.a);
+}
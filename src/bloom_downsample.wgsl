@@ -0,0 +1,44 @@
+source_texture: $0,0;
+source_sampler: $0,1;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+// 13 tap downsample from Call of Duty: Advanced Warfare
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_texture));
+    let a = textureSample(source_texture, source_sampler, uv + vec2(-2.0, -2.0) * texel).rgb;
+    let b = textureSample(source_texture, source_sampler, uv + vec2(0.0, -2.0) * texel).rgb;
+    let c = textureSample(source_texture, source_sampler, uv + vec2(2.0, -2.0) * texel).rgb;
+    let d = textureSample(source_texture, source_sampler, uv + vec2(-2.0, 0.0) * texel).rgb;
+    let e = textureSample(source_texture, source_sampler, uv).rgb;
+    let f = textureSample(source_texture, source_sampler, uv + vec2(2.0, 0.0) * texel).rgb;
+    let g = textureSample(source_texture, source_sampler, uv + vec2(-2.0, 2.0) * texel).rgb;
+    let h = textureSample(source_texture, source_sampler, uv + vec2(0.0, 2.0) * texel).rgb;
+    let i = textureSample(source_texture, source_sampler, uv + vec2(2.0, 2.0) * texel).rgb;
+    let j = textureSample(source_texture, source_sampler, uv + vec2(-1.0, -1.0) * texel).rgb;
+    let k = textureSample(source_texture, source_sampler, uv + vec2(1.0, -1.0) * texel).rgb;
+    let l = textureSample(source_texture, source_sampler, uv + vec2(-1.0, 1.0) * texel).rgb;
+    let m = textureSample(source_texture, source_sampler, uv + vec2(1.0, 1.0) * texel).rgb;
+    let color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+    return vec4(color, 1.0);
+}
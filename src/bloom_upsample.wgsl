@@ -0,0 +1,52 @@
+struct BloomSettings {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    filter_radius: f32,
+};
+
+low_texture: $0,0;
+low_sampler: $0,1;
+high_texture: $1,0;
+high_sampler: $1,1;
+settings: $2;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+// 3x3 tent filter over the smaller mip, added on top of the matching downsampled mip
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let r = settings.filter_radius / vec2<f32>(textureDimensions(low_texture));
+    var upsampled = textureSample(low_texture, low_sampler, uv).rgb * 4.0;
+    upsampled += (
+        textureSample(low_texture, low_sampler, uv + vec2(0.0, -r.y)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(-r.x, 0.0)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(r.x, 0.0)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(0.0, r.y)).rgb) * 2.0;
+    upsampled += 
+        textureSample(low_texture, low_sampler, uv + vec2(-r.x, -r.y)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(r.x, -r.y)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(-r.x, r.y)).rgb +
+        textureSample(low_texture, low_sampler, uv + vec2(r.x, r.y)).rgb;
+    upsampled /= 16.0;
+    return vec4(upsampled + textureSample(high_texture, high_sampler, uv).rgb, 1.0);
+}
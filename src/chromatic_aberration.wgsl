@@ -0,0 +1,40 @@
+struct ChromaticAberrationSettings {
+    intensity: f32,
+    padding1: f32,
+    padding2: f32,
+    padding3: f32,
+};
+
+previous_texture: $0,0;
+previous_sampler: $0,1;
+settings: $3;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the offset grows towards the edges of the screen
+    let offset = (in.tex_coords - vec2(0.5)) * settings.intensity;
+    let color = textureSample(previous_texture, previous_sampler, in.tex_coords);
+    let r = textureSample(previous_texture, previous_sampler, in.tex_coords + offset).r;
+    let b = textureSample(previous_texture, previous_sampler, in.tex_coords - offset).b;
+    return vec4(r, color.g, b, color.a);
+}
@@ -1,4 +1,4 @@
-use crate::{binding::{create_layout, Binding, UniformBinding, WgslType}, camera::Camera, compute::{ComputeOutput, ComputeShader}, model::Model, shader::ShaderType};
+use crate::{binding::{create_layout, Binding, UniformBinding, WgslType}, camera::Camera, compute::{ComputeOutput, ComputeShader}, instance::InstanceType, model::Model, shader::ShaderType};
 use bytemuck::{Pod, Zeroable};
 use cgmath::{vec3, Matrix4, Vector3};
 use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, Buffer, Device, Queue};
@@ -51,6 +51,11 @@ impl CullingCompute {
         }
     }
 
+    // Uses the WGSL struct of the instance type instead of passing it in by hand
+    pub fn for_instance<I: InstanceType>(device: &Device) -> Self {
+        Self::new(&I::wgsl_struct(), I::wgsl_matrix_field(), device)
+    }
+
     pub fn run(&mut self, input_buffer: &Buffer, num_instances: u32, bounding_box: &AABB, camera: &UniformBinding<Camera>, device: &Device, queue: &Queue) -> (Buffer, u32) {
         let output_buffer =
             device.create_buffer(&wgpu::BufferDescriptor {
@@ -0,0 +1,172 @@
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass, TextureFormat, TextureView};
+
+use crate::{binding::{create_layout, Binding, Descriptor, Resource, Uniform, UniformBinding}, model::Render, render_target::{RenderTarget, RenderTargetConfig}, shader::{ScenePipeline, Shader, ShaderConfig, ShaderType}, shadow::{ShadowConfig, ShadowMap}, surface_context::SurfaceCtx, texture::DepthTexture, window::BasicVertex};
+
+// albedo, world space normal and metallic/roughness/occlusion/lit, see gbuffer.wgsl
+pub const GBUFFER_FORMATS: [TextureFormat; 3] = [TextureFormat::Rgba8UnormSrgb, TextureFormat::Rgba16Float, TextureFormat::Rgba8Unorm];
+
+// The G-buffer as the lighting shader reads it, every texture is read with `textureLoad` so there is no sampler
+pub struct GBufferTextures {
+    pub views: Vec<TextureView>,
+    pub depth: TextureView,
+}
+
+impl Binding for GBufferTextures {
+    type LayoutConfig = ();
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    // the depth is bound as an unfilterable float texture as well, the GL backend can't `textureLoad` depth textures
+    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        (0..=GBUFFER_FORMATS.len() as u32).map(|binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        }).collect()
+    }
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        self.views.iter().chain([&self.depth]).map(|view| Resource::Bespoke(wgpu::BindingResource::TextureView(view))).collect()
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType { var_types: vec!["".into(); GBUFFER_FORMATS.len() + 1], wgsl_types: vec!["texture_2d<f32>".into(); GBUFFER_FORMATS.len() + 1] }
+    }
+}
+
+// Owned handles to the G-buffer attachments, so the G-buffer pass doesn't borrow the renderer while the handler draws into it
+pub struct GBufferPass {
+    colors: Vec<TextureView>,
+    depth: TextureView,
+}
+
+impl GBufferPass {
+    pub fn begin<'e>(&self, encoder: &'e mut CommandEncoder) -> RenderPass<'e> {
+        let color_attachments = self.colors.iter().map(|view| Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            depth_slice: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        })).collect::<Vec<_>>();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &color_attachments,
+            multiview_mask: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
+
+// Opaque geometry is drawn into the G-buffer by `WindowHandler::render_gbuffer`, then lit in one full screen pass that also
+// writes the depth into the scene texture, so `WindowHandler::render` can draw forward geometry on top before post processing.
+// The lighting shader gets the G-buffer at group 0, the camera at 1, a `LightSet` at 2 and the shadow map at 3
+pub struct DeferredRenderer {
+    pub gbuffer: RenderTarget,
+    gbuffer_layout: BindGroupLayout,
+    gbuffer_binding: BindGroup,
+    lighting_shader: ScenePipeline,
+    bind_groups: Vec<BindGroup>,
+    // only there when no shadow map is passed in, lights without shadow layers never sample it
+    _empty_shadows: Option<ShadowMap>,
+}
+
+impl DeferredRenderer {
+    pub fn new(surface_ctx: &dyn SurfaceCtx, camera: &dyn Uniform, lights: &dyn Uniform, shadows: Option<&ShadowMap>) -> Self {
+        let device = surface_ctx.device();
+        let (width, height) = (surface_ctx.scene_target().width, surface_ctx.scene_target().height);
+        let gbuffer = RenderTarget::new(device, "G-Buffer", width, height, RenderTargetConfig { formats: GBUFFER_FORMATS.to_vec(), depth: true, ..Default::default() });
+        let gbuffer_layout = create_layout::<GBufferTextures>((), device);
+        let gbuffer_binding = Self::bind_gbuffer(&gbuffer, &gbuffer_layout, device);
+        let empty_shadows = shadows.is_none().then(|| ShadowMap::new(device, ShadowConfig { resolution: 1, layers: 1, ..Default::default() }));
+        let shadows = &shadows.or(empty_shadows.as_ref()).unwrap().textures;
+        let uniforms: [&dyn Uniform; 3] = [camera, lights, shadows];
+        let layouts = uniforms.iter().map(|uniform| uniform.layout().clone()).collect::<Vec<_>>();
+        let shader_types = uniforms.iter().map(|uniform| uniform.shader_type().clone()).collect::<Vec<_>>();
+        let lighting_layout = gbuffer_layout.clone();
+        let lighting_shader = ScenePipeline::new(surface_ctx, move |device, scene_format, sample_count| {
+            Self::lighting_shader(device, scene_format, sample_count, &lighting_layout, &layouts, &shader_types)
+        });
+        Self {
+            gbuffer,
+            gbuffer_layout,
+            gbuffer_binding,
+            lighting_shader,
+            bind_groups: uniforms.iter().map(|uniform| uniform.binding().clone()).collect(),
+            _empty_shadows: empty_shadows,
+        }
+    }
+
+    // The config for shaders drawing into the G-buffer, they target `GBUFFER_FORMATS`
+    pub fn gbuffer_shader_config() -> ShaderConfig {
+        ShaderConfig { multisample_count: 1, ..Default::default() }
+    }
+
+    fn bind_gbuffer(gbuffer: &RenderTarget, layout: &BindGroupLayout, device: &Device) -> BindGroup {
+        let textures = GBufferTextures {
+            views: gbuffer.colors.iter().map(|color| color.value.view.clone()).collect(),
+            depth: gbuffer.depth_texture().unwrap().view.clone(),
+        };
+        UniformBinding::create_bind_group(&textures, "G-Buffer", layout, device).0
+    }
+
+    fn lighting_shader(device: &Device, scene_format: TextureFormat, sample_count: u32, gbuffer_layout: &BindGroupLayout, layouts: &[BindGroupLayout], shader_types: &[ShaderType]) -> Shader<'static> {
+        let gbuffer_type = GBufferTextures::shader_type(());
+        Shader::new(
+            "buildins/deferred_lighting.wgsl",
+            device,
+            vec![scene_format],
+            [gbuffer_layout].into_iter().chain(layouts).collect(),
+            [&gbuffer_type].into_iter().chain(shader_types).collect(),
+            vec![BasicVertex::desc()],
+            // the depth comes from the G-buffer so it always gets written
+            ShaderConfig { face_cull: None, depth_compare: wgpu::CompareFunction::Always, multisample_count: sample_count, ..Default::default() },
+        )
+    }
+
+    // Follows the size of the scene texture, called by the window before every frame
+    pub fn prepare(&mut self, surface_ctx: &dyn SurfaceCtx) {
+        let device = surface_ctx.device();
+        let scene_target = surface_ctx.scene_target();
+        if self.gbuffer.resize(device, scene_target.width, scene_target.height) {
+            self.gbuffer_binding = Self::bind_gbuffer(&self.gbuffer, &self.gbuffer_layout, device);
+        }
+    }
+
+    pub fn gbuffer_pass(&self) -> GBufferPass {
+        GBufferPass {
+            colors: self.gbuffer.colors.iter().map(|color| color.value.view.clone()).collect(),
+            depth: self.gbuffer.depth.as_ref().unwrap().value.view.clone(),
+        }
+    }
+
+    pub fn gbuffer_depth(&self) -> &DepthTexture {
+        self.gbuffer.depth_texture().unwrap()
+    }
+
+    // Lights the G-buffer into the scene texture, clearing it to its clear color first
+    pub fn compose(&self, surface_ctx: &dyn SurfaceCtx, encoder: &mut CommandEncoder) {
+        let mut render_pass = surface_ctx.scene_target().begin_render_pass(encoder, true);
+        self.lighting_shader.bind(surface_ctx, &mut render_pass);
+        render_pass.set_bind_group(0, &self.gbuffer_binding, &[]);
+        for (i, bind_group) in self.bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(1 + i as u32, bind_group, &[]);
+        }
+        surface_ctx.screen_model().render(&mut render_pass);
+    }
+}
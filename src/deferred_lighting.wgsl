@@ -0,0 +1,68 @@
+#include "buildins/lighting.wgsl"
+#include "buildins/shadows.wgsl"
+
+albedo_texture: $0,0;
+normal_texture: $0,1;
+material_texture: $0,2;
+depth_texture: $0,3;
+camera: $1;
+lights: $2;
+shadow_map: $3,0;
+shadow_sampler: $3,1;
+shadows: $3,2;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(depth_texture, pixel, 0).r;
+    // nothing was drawn here, the clear color stays
+    if depth >= 1.0 {
+        discard;
+    }
+    let albedo = textureLoad(albedo_texture, pixel, 0).rgb;
+    let material = textureLoad(material_texture, pixel, 0);
+    var out: FragmentOutput;
+    out.depth = depth;
+    if material.a < 0.5 {
+        out.color = vec4(albedo, 1.0);
+        return out;
+    }
+    let normal = normalize(textureLoad(normal_texture, pixel, 0).xyz);
+    let ndc = vec4(in.tex_coords.x * 2.0 - 1.0, 1.0 - in.tex_coords.y * 2.0, depth, 1.0);
+    let world = camera.inverse_proj * ndc;
+    let position = world.xyz / world.w;
+    let view_dir = normalize(camera.position - position);
+    var color = ambient_light(lights.ambient, albedo, material.b);
+    for (var i = 0u; i < lights.count; i++) {
+        let light = lights.lights[i];
+        let shadow = shadow_factor(light, shadows, shadow_map, shadow_sampler, position, normal, camera.position);
+        color += pbr_light(light, position, normal, view_dir, albedo, material.r, material.g) * shadow;
+    }
+    out.color = vec4(color, 1.0);
+    return out;
+}
@@ -0,0 +1,54 @@
+struct FogSettings {
+    color: vec4<f32>,
+    density: f32,
+    start: f32,
+    end: f32,
+    // 0 = linear, 1 = exponential, 2 = exponential squared
+    mode: u32,
+};
+
+previous_texture: $0,0;
+previous_sampler: $0,1;
+depth_texture: $2,0;
+settings: $3;
+camera: $4;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(previous_texture, previous_sampler, in.tex_coords);
+    let depth = textureLoad(depth_texture, vec2<i32>(in.clip_position.xy), 0);
+    let ndc = vec4(in.tex_coords.x * 2.0 - 1.0, 1.0 - in.tex_coords.y * 2.0, depth, 1.0);
+    let world = camera.inverse_proj * ndc;
+    let dist = distance(world.xyz / world.w, camera.position);
+    var fog = 0.0;
+    if settings.mode == 0u {
+        fog = clamp((dist - settings.start) / (settings.end - settings.start), 0.0, 1.0);
+    } else if settings.mode == 1u {
+        fog = 1.0 - exp(-settings.density * max(dist - settings.start, 0.0));
+    } else {
+        let d = settings.density * max(dist - settings.start, 0.0);
+        fog = 1.0 - exp(-d * d);
+    }
+    fog *= settings.color.a;
+    return vec4(mix(color.rgb, settings.color.rgb, fog), color.a);
+}
@@ -0,0 +1,65 @@
+struct FxaaSettings {
+    span_max: f32,
+    reduce_min: f32,
+    reduce_mul: f32,
+    padding: f32,
+};
+
+previous_texture: $0,0;
+previous_sampler: $0,1;
+settings: $3;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(previous_texture));
+    let uv = in.tex_coords;
+    let color_m = textureSample(previous_texture, previous_sampler, uv);
+    let luma_nw = luma(textureSample(previous_texture, previous_sampler, uv + vec2(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(textureSample(previous_texture, previous_sampler, uv + vec2(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(textureSample(previous_texture, previous_sampler, uv + vec2(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(textureSample(previous_texture, previous_sampler, uv + vec2(1.0, 1.0) * texel).rgb);
+    let luma_m = luma(color_m.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * settings.reduce_mul, settings.reduce_min);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-settings.span_max), vec2(settings.span_max)) * texel;
+
+    let rgb_a = 0.5 * (
+        textureSample(previous_texture, previous_sampler, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(previous_texture, previous_sampler, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(previous_texture, previous_sampler, uv + dir * -0.5).rgb +
+        textureSample(previous_texture, previous_sampler, uv + dir * 0.5).rgb);
+    let luma_b = luma(rgb_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4(rgb_a, color_m.a);
+    }
+    return vec4(rgb_b, color_m.a);
+}
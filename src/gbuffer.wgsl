@@ -0,0 +1,19 @@
+// Outputs for shaders drawing into the `DeferredRenderer` G-buffer, pulled in with `#include "buildins/gbuffer.wgsl"`.
+// Every target is written with an alpha of 1 since the pipelines blend
+
+struct GBufferOutput {
+    @location(0) albedo: vec4f,
+    // world space
+    @location(1) normal: vec4f,
+    // metallic, roughness, occlusion and 1 for lit surfaces
+    @location(2) material: vec4f,
+};
+
+fn gbuffer_output(albedo: vec3f, normal: vec3f, metallic: f32, roughness: f32, occlusion: f32) -> GBufferOutput {
+    return GBufferOutput(vec4f(albedo, 1.0), vec4f(normalize(normal), 1.0), vec4f(metallic, roughness, occlusion, 1.0));
+}
+
+// Shows the color as it is without any lighting
+fn gbuffer_unlit(color: vec3f) -> GBufferOutput {
+    return GBufferOutput(vec4f(color, 1.0), vec4f(0.0, 0.0, 1.0, 1.0), vec4f(0.0, 1.0, 1.0, 0.0));
+}
@@ -2,4 +2,4 @@ struct Camera {
     view_proj: mat4x4f,
     inverse_proj: mat4x4f,
     position: vec3f,
-};
\ No newline at end of file
+};
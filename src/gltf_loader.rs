@@ -0,0 +1,441 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use bytemuck::{bytes_of, Pod};
+use cgmath::{Matrix4, SquareMatrix};
+use gltf::{animation::util::ReadOutputs, image::Source as ImageSource, mesh::util::ReadIndices, texture::{MagFilter, WrappingMode}};
+
+use crate::{animation::{AnimationClip, Channel, Interpolation, Property, SkinnedVertex, Skeleton, Transform}, binding::Descriptor, mesh::{AlphaMode, DefaultTextures, Material, MaterialLayout, MaterialMap, MaterialUniform, MeshModel, PbrFactors, PbrMaterial, PbrTexture}, mesh_processing::{generate_normals, generate_tangents, optimize_mesh, MeshProcessingOptions, NormalMode, OptimizationReport}, model::{calculate_bounding_box, Model, ToRaw}, resource_loader::load_resource, texture::Texture, VertexTrait};
+
+pub struct GltfNode {
+    pub name: Option<String>,
+    // relative to the parent node
+    pub transform: Matrix4<f32>,
+    // `transform` split up, the starting point for animations
+    pub rest: Transform,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // index into `GltfModel::meshes`
+    pub mesh: Option<usize>,
+    // index into `GltfModel::skins`, the mesh then uses `SkinnedVertex`
+    pub skin: Option<usize>,
+}
+
+pub struct GltfModel {
+    pub nodes: Vec<GltfNode>,
+    // the nodes of the default scene, or every node without a parent if there is no scene
+    pub roots: Vec<usize>,
+    // one MeshModel per glTF mesh with one Model per primitive, they all share the same materials.
+    // Primitives with joints and weights use `SkinnedVertex`, the rest use `GltfVertex`
+    pub meshes: Vec<MeshModel>,
+    pub skins: Vec<Skeleton>,
+    pub animations: Vec<AnimationClip>,
+    // primitives that aren't triangle lists are left out of `meshes`
+    pub skipped_primitives: usize,
+}
+
+impl GltfModel {
+    // Loads a .gltf or .glb, external buffers and images are resolved relative to the file with `load_resource`.
+    // The base color is bound as the `MaterialMap::Diffuse` map, the other glTF maps use their own `MaterialMap`
+    pub fn load(
+        source_path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+    ) -> anyhow::Result<GltfModel> {
+        Self::load_with(source_path, device, queue, layout, &MeshProcessingOptions::default())
+    }
+
+    // Missing normals are always flat as the spec asks, `options.normals` is only used with `recompute_normals`.
+    // Tangents are generated whenever the file has none, so `options.tangents` doesn't change anything
+    pub fn load_with(
+        source_path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+        options: &MeshProcessingOptions,
+    ) -> anyhow::Result<GltfModel> {
+        let path_string = source_path.as_os_str().to_str().unwrap();
+        let bytes = load_resource(path_string).ok_or(anyhow!("tried to load resource at {path_string}"))?;
+        let gltf = gltf::Gltf::from_slice(&bytes)?;
+        let parent = source_path.parent().unwrap_or(Path::new(""));
+
+        let mut buffers = vec![];
+        for buffer in gltf.buffers() {
+            let mut data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf.blob.clone().ok_or(anyhow!("glTF buffer {} refers to a missing GLB chunk", buffer.index()))?,
+                gltf::buffer::Source::Uri(uri) => load_uri(uri, parent)?,
+            };
+            if data.len() < buffer.length() {
+                bail!("glTF buffer {} is {} bytes, expected {}", buffer.index(), data.len(), buffer.length());
+            }
+            data.truncate(buffer.length());
+            buffers.push(data);
+        }
+
+        let mut textures = TextureCache { images: HashMap::new(), parent, buffers: &buffers, device, queue };
+        let defaults = DefaultTextures::new(device, queue)?;
+        let mut materials = vec![];
+        for material in gltf.materials() {
+            materials.push(load_material(&material, &mut textures, layout, &defaults)?);
+        }
+        // primitives without a material use the glTF default material, which goes at the end of the list
+        let default_material = materials.len();
+        if let Some(primitive) = gltf.meshes().flat_map(|mesh| mesh.primitives()).find(|primitive| primitive.material().index().is_none()) {
+            materials.push(load_material(&primitive.material(), &mut textures, layout, &defaults)?);
+        }
+
+        let mut meshes = vec![];
+        let mut skipped_primitives = 0;
+        for mesh in gltf.meshes() {
+            let mut models = vec![];
+            let mut model_materials = vec![];
+            let mut optimization = vec![];
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    skipped_primitives += 1;
+                    continue;
+                }
+                let (model, report) = load_primitive(&primitive, &buffers, device, options)?;
+                models.push(model);
+                optimization.extend(report);
+                model_materials.push(primitive.material().index().unwrap_or(default_material));
+            }
+            meshes.push(MeshModel { models, model_materials, materials: materials.clone(), enable_material_binding: true, material_group: 0, optimization });
+        }
+
+        let mut nodes = gltf.nodes().map(|node| GltfNode {
+            name: node.name().map(|name| name.to_string()),
+            transform: Matrix4::from(node.transform().matrix()),
+            rest: Transform::from_decomposed(node.transform().decomposed()),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            skin: node.skin().map(|skin| skin.index()),
+        }).collect::<Vec<_>>();
+        for i in 0..nodes.len() {
+            for child in nodes[i].children.clone() {
+                nodes[child].parent = Some(i);
+            }
+        }
+        let roots = match gltf.default_scene().or(gltf.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len()).filter(|i| nodes[*i].parent.is_none()).collect(),
+        };
+
+        let skins = gltf.skins().map(|skin| {
+            let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+            let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices.map(Matrix4::from).collect(),
+                None => vec![Matrix4::identity(); joints.len()],
+            };
+            Skeleton { name: skin.name().map(|name| name.to_string()), joints, inverse_bind_matrices }
+        }).collect();
+
+        let animations = gltf.animations().map(|animation| {
+            let channels = animation.channels().filter_map(|channel| {
+                let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+                let times = reader.read_inputs()?.collect::<Vec<_>>();
+                let (property, values) = match reader.read_outputs()? {
+                    ReadOutputs::Translations(values) => (Property::Translation, values.map(|[x, y, z]| [x, y, z, 0.0]).collect::<Vec<_>>()),
+                    ReadOutputs::Rotations(values) => (Property::Rotation, values.into_f32().collect()),
+                    ReadOutputs::Scales(values) => (Property::Scale, values.map(|[x, y, z]| [x, y, z, 0.0]).collect()),
+                    ReadOutputs::MorphTargetWeights(_) => return None,
+                };
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+                Some(Channel { node: channel.target().node().index(), property, interpolation, times, values })
+            }).collect::<Vec<_>>();
+            let duration = channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max);
+            AnimationClip { name: animation.name().unwrap_or("").to_string(), duration, channels }
+        }).collect();
+
+        Ok(GltfModel { nodes, roots, meshes, skins, animations, skipped_primitives })
+    }
+
+    // The transform of every node relative to the model, indexed like `nodes`
+    pub fn world_transforms(&self) -> Vec<Matrix4<f32>> {
+        self.pose_world_transforms(&self.rest_pose())
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.nodes.iter().map(|node| node.rest).collect()
+    }
+
+    // Like `world_transforms` but with local transforms from `pose`, e.g. after `AnimationClip::sample`
+    pub fn pose_world_transforms(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut transforms = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack = self.roots.iter().map(|root| (*root, Matrix4::identity())).collect::<Vec<_>>();
+        while let Some((i, parent_transform)) = stack.pop() {
+            transforms[i] = parent_transform * pose[i].to_matrix();
+            stack.extend(self.nodes[i].children.iter().map(|child| (*child, transforms[i])));
+        }
+        transforms
+    }
+
+    pub fn find_animation(&self, name: &str) -> Option<&AnimationClip> {
+        self.animations.iter().find(|animation| animation.name == name)
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name.as_deref() == Some(name))
+    }
+}
+
+fn load_uri(uri: &str, parent: &Path) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let Some((_, encoded)) = data.split_once(";base64,") else { bail!("only base64 data uris are supported") };
+        return Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?);
+    }
+    let path = parent.join(uri.replace("%20", " "));
+    let path_string = path.as_os_str().to_str().unwrap();
+    load_resource(path_string).ok_or(anyhow!("tried to load resource at {path_string}"))
+}
+
+struct TextureCache<'a> {
+    // keyed by image index and whether it holds color data
+    images: HashMap<(usize, bool), Texture>,
+    parent: &'a Path,
+    buffers: &'a Vec<Vec<u8>>,
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+}
+
+impl TextureCache<'_> {
+    fn load(&mut self, texture: gltf::Texture, srgb: bool) -> anyhow::Result<Texture> {
+        let image = texture.source();
+        let key = (image.index(), srgb);
+        if !self.images.contains_key(&key) {
+            let bytes = match image.source() {
+                ImageSource::View { view, .. } => self.buffers[view.buffer().index()][view.offset()..view.offset() + view.length()].to_vec(),
+                ImageSource::Uri { uri, .. } => load_uri(uri, self.parent)?,
+            };
+            let img = image::load_from_memory(&bytes)?;
+            let format = if srgb { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm };
+            self.images.insert(key, Texture::from_image(self.device, self.queue, &img, image.name(), Some(format), None, None, None)?);
+        }
+        let sampler = texture.sampler();
+        let filter_mode = match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        let address_mode = |wrapping| match wrapping {
+            WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+        };
+        Ok(self.images[&key].clone().with_address_modes(self.device, filter_mode, address_mode(sampler.wrap_s()), address_mode(sampler.wrap_t())))
+    }
+}
+
+fn load_material(material: &gltf::Material, textures: &mut TextureCache, layout: &MaterialLayout, defaults: &DefaultTextures) -> anyhow::Result<Material> {
+    let pbr = material.pbr_metallic_roughness();
+    let base_color_texture = match pbr.base_color_texture() {
+        Some(info) => Some(PbrTexture { texture: textures.load(info.texture(), true)?, tex_coord: info.tex_coord() }),
+        None => None,
+    };
+    let metallic_roughness_texture = match pbr.metallic_roughness_texture() {
+        Some(info) => Some(PbrTexture { texture: textures.load(info.texture(), false)?, tex_coord: info.tex_coord() }),
+        None => None,
+    };
+    let normal_texture = match material.normal_texture() {
+        Some(info) => Some(PbrTexture { texture: textures.load(info.texture(), false)?, tex_coord: info.tex_coord() }),
+        None => None,
+    };
+    let occlusion_texture = match material.occlusion_texture() {
+        Some(info) => Some(PbrTexture { texture: textures.load(info.texture(), false)?, tex_coord: info.tex_coord() }),
+        None => None,
+    };
+    let emissive_texture = match material.emissive_texture() {
+        Some(info) => Some(PbrTexture { texture: textures.load(info.texture(), true)?, tex_coord: info.tex_coord() }),
+        None => None,
+    };
+    let factors = PbrFactors {
+        base_color: pbr.base_color_factor(),
+        emissive: material.emissive_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        normal_scale: material.normal_texture().map(|it| it.scale()).unwrap_or(1.0),
+        occlusion_strength: material.occlusion_texture().map(|it| it.strength()).unwrap_or(1.0),
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+    };
+    let double_sided = material.double_sided();
+    let alpha_mode = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+    };
+    let mut maps = HashMap::new();
+    for (map, texture) in [
+        (MaterialMap::Diffuse, &base_color_texture),
+        (MaterialMap::MetallicRoughness, &metallic_roughness_texture),
+        (MaterialMap::Normal, &normal_texture),
+        (MaterialMap::Occlusion, &occlusion_texture),
+        (MaterialMap::Emissive, &emissive_texture),
+    ] {
+        if let Some(texture) = texture {
+            maps.insert(map, texture.texture.clone());
+        }
+    }
+    let values = MaterialUniform {
+        diffuse: [factors.base_color[0], factors.base_color[1], factors.base_color[2]],
+        dissolve: factors.base_color[3],
+        emissive: factors.emissive,
+        ..Default::default()
+    };
+    let mut material = Material::new(textures.device, material.name().unwrap_or("").to_string(), values, maps, layout, defaults);
+    material.pbr = Some(PbrMaterial {
+        factors,
+        base_color_texture,
+        metallic_roughness_texture,
+        normal_texture,
+        occlusion_texture,
+        emissive_texture,
+        alpha_mode,
+        double_sided,
+    });
+    Ok(material)
+}
+
+fn load_primitive(primitive: &gltf::Primitive, buffers: &[Vec<u8>], device: &wgpu::Device, options: &MeshProcessingOptions) -> anyhow::Result<(Model, Option<OptimizationReport>)> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+    let mut positions = reader.read_positions().ok_or(anyhow!("glTF primitive has no positions"))?.collect::<Vec<_>>();
+    let mut normals = reader.read_normals().map(|it| it.collect::<Vec<_>>()).unwrap_or_default();
+    let mut tangents = reader.read_tangents().map(|it| it.collect::<Vec<_>>()).unwrap_or_default();
+    let mut tex_coords = reader.read_tex_coords(0).map(|it| it.into_f32().collect::<Vec<_>>()).unwrap_or_default();
+    let mut tex_coords1 = reader.read_tex_coords(1).map(|it| it.into_f32().collect::<Vec<_>>()).unwrap_or_default();
+    let mut joints = reader.read_joints(0).map(|it| it.into_u16().collect::<Vec<_>>()).unwrap_or_default();
+    let mut weights = reader.read_weights(0).map(|it| it.into_f32().collect::<Vec<_>>()).unwrap_or_default();
+    let skinned = !joints.is_empty() && !weights.is_empty();
+    // u8 indices aren't supported by wgpu so they become u16
+    let (mut indices, wide_indices) = match reader.read_indices() {
+        Some(ReadIndices::U8(indices)) => (indices.map(|index| index as u32).collect::<Vec<_>>(), false),
+        Some(ReadIndices::U16(indices)) => (indices.map(|index| index as u32).collect(), false),
+        Some(ReadIndices::U32(indices)) => (indices.collect(), true),
+        None => ((0..positions.len() as u32).collect(), positions.len() > u16::MAX as usize),
+    };
+    // the spec asks for flat normals when they're missing
+    if normals.len() < positions.len() || options.recompute_normals {
+        let mode = if normals.len() < positions.len() { NormalMode::Flat } else { options.normals };
+        let generated = generate_normals(&positions, &indices, mode);
+        positions = remap_attribute(&positions, &generated.remap);
+        tangents = remap_attribute(&tangents, &generated.remap);
+        tex_coords = remap_attribute(&tex_coords, &generated.remap);
+        tex_coords1 = remap_attribute(&tex_coords1, &generated.remap);
+        joints = remap_attribute(&joints, &generated.remap);
+        weights = remap_attribute(&weights, &generated.remap);
+        indices = generated.indices;
+        normals = generated.normals;
+    }
+    if tangents.len() < positions.len() {
+        tangents = generate_tangents(&positions, &normals, &tex_coords, &indices);
+    }
+    let vertices = positions.iter().enumerate().map(|(i, position)| GltfVertex {
+        position: *position,
+        tex_coords: tex_coords.get(i).copied().unwrap_or_default(),
+        normal: normals[i],
+        tangent: tangents[i],
+        tex_coords1: tex_coords1.get(i).copied().unwrap_or_default(),
+    }).collect::<Vec<_>>();
+    if skinned {
+        let vertices = vertices.iter().enumerate().map(|(i, vertex)| SkinnedVertex {
+            position: vertex.position,
+            tex_coords: vertex.tex_coords,
+            normal: vertex.normal,
+            tangent: vertex.tangent,
+            tex_coords1: vertex.tex_coords1,
+            joints: joints.get(i).copied().unwrap_or_default().map(|joint| joint as u32),
+            weights: weights.get(i).copied().unwrap_or_default(),
+        }).collect::<Vec<_>>();
+        return Ok(indexed_model(vertices, indices, wide_indices, options.optimize, device));
+    }
+    Ok(indexed_model(vertices, indices, wide_indices, options.optimize, device))
+}
+
+fn remap_attribute<T: Copy>(values: &[T], remap: &[u32]) -> Vec<T> {
+    if values.is_empty() {
+        return vec![];
+    }
+    remap.iter().map(|i| values[*i as usize]).collect()
+}
+
+// keeps the index width of the file unless splitting vertices made them too big for u16, optimizing picks the smallest that fits
+fn indexed_model<V: Pod + ToRaw + VertexTrait>(vertices: Vec<V>, indices: Vec<u32>, wide_indices: bool, optimize: bool, device: &wgpu::Device) -> (Model, Option<OptimizationReport>) {
+    let index_format = if wide_indices || vertices.len() > u16::MAX as usize + 1 { wgpu::IndexFormat::Uint32 } else { wgpu::IndexFormat::Uint16 };
+    let (vertices, indices, index_format, report) = if optimize {
+        let (vertices, indices, report) = optimize_mesh(vertices, indices, index_format);
+        (vertices, indices, report.after.index_format, Some(report))
+    } else {
+        (vertices, indices, index_format, None)
+    };
+    let bounding_box = calculate_bounding_box(&vertices);
+    let model = match index_format {
+        wgpu::IndexFormat::Uint32 => Model::new(vertices, &indices, bounding_box, device),
+        wgpu::IndexFormat::Uint16 => Model::new(vertices, &indices.iter().map(|index| *index as u16).collect::<Vec<_>>(), bounding_box, device),
+    };
+    (model, report)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct GltfVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    // w is the handedness of the bitangent
+    pub tangent: [f32; 4],
+    pub tex_coords1: [f32; 2],
+}
+
+impl VertexTrait for GltfVertex {
+    fn pos(&self) -> cgmath::Vector3<f32> {
+        self.position.into()
+    }
+}
+
+impl Descriptor for GltfVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+impl ToRaw for GltfVertex {
+    fn to_raw(&self) -> Vec<u8> {
+        bytes_of(self).to_vec()
+    }
+}
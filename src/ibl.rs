@@ -0,0 +1,485 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use bytemuck::{Pod, Zeroable};
+use wgpu::{Device, Queue, TextureFormat, TextureView, TextureViewDimension};
+
+use crate::{binding::{Binding, Resource, UniformBinding, WgslType}, compute::ComputeShader, shader::ShaderType, texture::{create_texture, Texture}};
+
+pub const IBL_MAGIC: [u8; 4] = *b"BIBL";
+pub const IBL_VERSION: u32 = 2;
+pub const IBL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const TEXEL_BYTES: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IblConfig {
+    pub irradiance_size: u32,
+    pub specular_size: u32,
+    // roughness goes from 0 at the first mip to 1 at the last
+    pub specular_mips: u32,
+    pub brdf_size: u32,
+    // per texel, for all three maps
+    pub samples: u32,
+}
+
+impl Default for IblConfig {
+    fn default() -> Self {
+        Self { irradiance_size: 32, specular_size: 128, specular_mips: 5, brdf_size: 256, samples: 1024 }
+    }
+}
+
+impl IblConfig {
+    pub fn validate(&self, device: &Device) -> anyhow::Result<()> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        for (name, size) in [("irradiance", self.irradiance_size), ("specular", self.specular_size), ("BRDF LUT", self.brdf_size)] {
+            if size == 0 || size > max_size {
+                bail!("a {size} texel {name} map isn't supported, the size has to be between 1 and {max_size}");
+            }
+        }
+        if self.specular_mips == 0 || self.specular_size.checked_shr(self.specular_mips - 1).unwrap_or(0) == 0 {
+            bail!("a {} texel specular map doesn't have {} mips", self.specular_size, self.specular_mips);
+        }
+        Ok(())
+    }
+
+    // the size of the texels in the cache file, None when it doesn't fit in memory
+    fn byte_len(&self) -> Option<usize> {
+        let mut length = 0u64;
+        for (size, layers, mips) in [(self.irradiance_size, 6, 1), (self.specular_size, 6, self.specular_mips), (self.brdf_size, 1, 1)] {
+            for mip in 0..mips {
+                let size = u64::from(size.checked_shr(mip).unwrap_or(0));
+                length = length.checked_add(size.checked_mul(size)?.checked_mul(layers)?.checked_mul(u64::from(TEXEL_BYTES))?)?;
+            }
+        }
+        usize::try_from(length).ok()
+    }
+}
+
+// The file is the header followed by the Rgba16Float texels of the irradiance faces, the specular faces mip by mip
+// and the BRDF LUT, tightly packed and little endian. The environment hash tells the cubemap
+// the maps were computed from apart, see `environment_hash`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct IblHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub irradiance_size: u32,
+    pub specular_size: u32,
+    pub specular_mips: u32,
+    pub brdf_size: u32,
+    pub samples: u32,
+    pub padding: u32,
+    pub environment_hash: u64,
+}
+
+impl IblHeader {
+    fn config(&self) -> IblConfig {
+        IblConfig { irradiance_size: self.irradiance_size, specular_size: self.specular_size, specular_mips: self.specular_mips, brdf_size: self.brdf_size, samples: self.samples }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+struct IblParams {
+    roughness: f32,
+    size: u32,
+    samples: u32,
+    face: u32,
+}
+
+impl WgslType for IblParams {
+    fn wgsl_name() -> String {
+        "IblParams".into()
+    }
+}
+
+// The texture a pass writes to. Cube faces go through a 2D scratch texture and get copied into the cubemap, a
+// cube compatible texture can't be bound as a 2D array storage texture on GL
+struct IblTarget {
+    view: TextureView,
+}
+
+impl Binding for IblTarget {
+    type LayoutConfig = ();
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture { access: wgpu::StorageTextureAccess::WriteOnly, format: IBL_FORMAT, view_dimension: TextureViewDimension::D2 },
+                count: None,
+            },
+        ]
+    }
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        vec![Resource::Bespoke(wgpu::BindingResource::TextureView(&self.view))]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["".into()],
+            wgsl_types: vec!["texture_storage_2d<rgba16float, write>".into()],
+        }
+    }
+}
+
+// Image based lighting from an environment cubemap: diffuse irradiance, specular prefiltered per roughness mip and the
+// BRDF LUT of the split sum. Bound as a group they show up as two cubes, the LUT and a sampler, see ibl.wgsl
+pub struct IblMaps {
+    pub config: IblConfig,
+    pub environment_hash: u64,
+    pub irradiance: Texture,
+    pub specular: Texture,
+    pub brdf: Texture,
+}
+
+impl IblMaps {
+    fn empty(device: &Device, config: IblConfig, environment_hash: u64) -> Self {
+        Self {
+            config,
+            environment_hash,
+            irradiance: ibl_texture(device, "IBL Irradiance", config.irradiance_size, 6, 1),
+            specular: ibl_texture(device, "IBL Specular", config.specular_size, 6, config.specular_mips),
+            brdf: ibl_texture(device, "IBL BRDF LUT", config.brdf_size, 1, 1),
+        }
+    }
+
+    // `environment` has to be a cubemap, see `Texture::cubemap_from_images`
+    pub fn compute(device: &Device, queue: &Queue, environment: &Texture, config: IblConfig) -> anyhow::Result<Self> {
+        if environment.dimensions != TextureViewDimension::Cube {
+            bail!("the IBL environment has to be a cubemap");
+        }
+        let environment_hash = environment_hash(device, queue, environment)?;
+        Self::compute_with_hash(device, queue, environment, config, environment_hash)
+    }
+
+    fn compute_with_hash(device: &Device, queue: &Queue, environment: &Texture, config: IblConfig, environment_hash: u64) -> anyhow::Result<Self> {
+        config.validate(device)?;
+        let maps = Self::empty(device, config, environment_hash);
+        let environment = UniformBinding::new(device, "IBL Environment", environment.clone(), None);
+        let mut passes = FacePasses::new(device, config.irradiance_size.max(config.specular_size));
+        let irradiance_shader = passes.shader(device, include_str!("ibl_irradiance.wgsl"), &environment);
+        let specular_shader = passes.shader(device, include_str!("ibl_specular.wgsl"), &environment);
+        for (shader, texture) in [(&irradiance_shader, &maps.irradiance), (&specular_shader, &maps.specular)] {
+            let mips = texture.texture.mip_level_count();
+            for mip in 0..mips {
+                let size = texture.size.width >> mip;
+                for face in 0..6 {
+                    let roughness = mip as f32 / (mips - 1).max(1) as f32;
+                    passes.run(device, queue, shader, &environment, IblParams { roughness, size, samples: config.samples, face });
+                    let mut encoder = device.create_command_encoder(&Default::default());
+                    encoder.copy_texture_to_texture(
+                        passes.scratch.texture.as_image_copy(),
+                        wgpu::TexelCopyTextureInfo { texture: &texture.texture, mip_level: mip, origin: wgpu::Origin3d { x: 0, y: 0, z: face }, aspect: wgpu::TextureAspect::All },
+                        wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+                    );
+                    queue.submit([encoder.finish()]);
+                }
+            }
+        }
+
+        let mut params = passes.params;
+        params.set_data(queue, IblParams { roughness: 0.0, size: config.brdf_size, samples: config.samples, face: 0 });
+        let target = UniformBinding::new(device, "IBL BRDF LUT", IblTarget { view: maps.brdf.view.clone() }, None);
+        let brdf_shader = ComputeShader::new(include_str!("ibl_brdf.wgsl"), vec![&params.layout, &target.layout], vec![&params.shader_type, &target.shader_type], device);
+        brdf_shader.run_once(vec![&params.binding, &target.binding], [config.brdf_size.div_ceil(8), config.brdf_size.div_ceil(8), 1], device, queue);
+        Ok(maps)
+    }
+
+    // Loads the maps cached at `path`, or computes and caches them when the file is missing or was made with another config
+    // or environment. Checking the environment reads it back from the GPU
+    pub fn load_or_compute(device: &Device, queue: &Queue, environment: &Texture, config: IblConfig, path: &Path) -> anyhow::Result<Self> {
+        if environment.dimensions != TextureViewDimension::Cube {
+            bail!("the IBL environment has to be a cubemap");
+        }
+        let environment_hash = environment_hash(device, queue, environment)?;
+        if let Ok(bytes) = std::fs::read(path) {
+            if let Ok(maps) = Self::from_bytes(device, queue, &bytes) {
+                if maps.config == config && maps.environment_hash == environment_hash {
+                    return Ok(maps);
+                }
+            }
+        }
+        let maps = Self::compute_with_hash(device, queue, environment, config, environment_hash)?;
+        maps.save(device, queue, path)?;
+        Ok(maps)
+    }
+
+    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8]) -> anyhow::Result<Self> {
+        let header_size = std::mem::size_of::<IblHeader>();
+        if bytes.len() < header_size {
+            bail!("IBL cache is too short for its header");
+        }
+        let header: IblHeader = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        if header.magic != IBL_MAGIC {
+            bail!("not an IBL cache");
+        }
+        if header.version != IBL_VERSION {
+            bail!("IBL cache version {} isn't supported, expected {IBL_VERSION}", header.version);
+        }
+        let config = header.config();
+        config.validate(device)?;
+        let length = config.byte_len().and_then(|length| length.checked_add(header_size)).ok_or(anyhow!("IBL cache is too large"))?;
+        if bytes.len() != length {
+            bail!("IBL cache is {} bytes, expected {length}", bytes.len());
+        }
+        // the lengths below can't overflow, they add up to the file length
+        let maps = Self::empty(device, config, header.environment_hash);
+        let mut offset = header_size;
+        for (texture, layers) in [(&maps.irradiance, 6), (&maps.specular, 6), (&maps.brdf, 1)] {
+            for mip in 0..texture.texture.mip_level_count() {
+                let size = texture.size.width >> mip;
+                let length = size as usize * size as usize * layers as usize * TEXEL_BYTES as usize;
+                let data = &bytes[offset..offset + length];
+                offset += length;
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture.texture,
+                        mip_level: mip,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    data,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(size * TEXEL_BYTES),
+                        rows_per_image: Some(size),
+                    },
+                    wgpu::Extent3d { width: size, height: size, depth_or_array_layers: layers },
+                );
+            }
+        }
+        Ok(maps)
+    }
+
+    // Reads the maps back from the GPU, this waits for the device
+    pub fn to_bytes(&self, device: &Device, queue: &Queue) -> anyhow::Result<Vec<u8>> {
+        let header = IblHeader {
+            magic: IBL_MAGIC,
+            version: IBL_VERSION,
+            irradiance_size: self.config.irradiance_size,
+            specular_size: self.config.specular_size,
+            specular_mips: self.config.specular_mips,
+            brdf_size: self.config.brdf_size,
+            samples: self.config.samples,
+            padding: 0,
+            environment_hash: self.environment_hash,
+        };
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        // cube faces are sampled out into a 2D texture first, reading a cubemap straight into a buffer gives zeros on GL
+        let mut passes = FacePasses::new(device, self.config.irradiance_size.max(self.config.specular_size));
+        for texture in [&self.irradiance, &self.specular] {
+            // nearest so the texel centers come out exactly
+            let cube = UniformBinding::new(device, "IBL Read Cube", texture.clone().with_sampler(device, wgpu::FilterMode::Nearest, wgpu::AddressMode::ClampToEdge), None);
+            let shader = passes.shader(device, include_str!("ibl_read.wgsl"), &cube);
+            for mip in 0..texture.texture.mip_level_count() {
+                let size = texture.size.width >> mip;
+                for face in 0..6 {
+                    passes.run(device, queue, &shader, &cube, IblParams { roughness: mip as f32, size, samples: 0, face });
+                    bytes.extend(read_face(device, queue, &passes.scratch.texture, size)?);
+                }
+            }
+        }
+        bytes.extend(read_face(device, queue, &self.brdf.texture, self.config.brdf_size)?);
+        Ok(bytes)
+    }
+
+    pub fn save(&self, device: &Device, queue: &Queue, path: &Path) -> anyhow::Result<()> {
+        Ok(std::fs::write(path, self.to_bytes(device, queue)?)?)
+    }
+}
+
+impl Binding for IblMaps {
+    type LayoutConfig = ();
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        vec![
+            texture(0, TextureViewDimension::Cube),
+            texture(1, TextureViewDimension::Cube),
+            texture(2, TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        vec![
+            Resource::Bespoke(wgpu::BindingResource::TextureView(&self.irradiance.view)),
+            Resource::Bespoke(wgpu::BindingResource::TextureView(&self.specular.view)),
+            Resource::Bespoke(wgpu::BindingResource::TextureView(&self.brdf.view)),
+            Resource::Bespoke(wgpu::BindingResource::Sampler(&self.specular.sampler)),
+        ]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["".into(); 4],
+            wgsl_types: vec!["texture_cube<f32>".into(), "texture_cube<f32>".into(), "texture_2d<f32>".into(), "sampler".into()],
+        }
+    }
+}
+
+// Runs a shader over one cube face at a time into a 2D scratch texture, the shaders get the cube at group 0,
+// `IblParams` at 1 and the scratch texture at 2
+struct FacePasses {
+    params: UniformBinding<IblParams>,
+    scratch: Texture,
+    target: UniformBinding<IblTarget>,
+}
+
+impl FacePasses {
+    fn new(device: &Device, size: u32) -> Self {
+        let scratch = ibl_texture(device, "IBL Scratch", size, 1, 1);
+        Self {
+            params: UniformBinding::new(device, "IBL Params", IblParams { roughness: 0.0, size: 0, samples: 0, face: 0 }, None),
+            target: UniformBinding::new(device, "IBL Scratch", IblTarget { view: scratch.view.clone() }, None),
+            scratch,
+        }
+    }
+
+    fn shader(&self, device: &Device, source: &str, cube: &UniformBinding<Texture>) -> ComputeShader {
+        ComputeShader::new(source, vec![&cube.layout, &self.params.layout, &self.target.layout], vec![&cube.shader_type, &self.params.shader_type, &self.target.shader_type], device)
+    }
+
+    fn run(&mut self, device: &Device, queue: &Queue, shader: &ComputeShader, cube: &UniformBinding<Texture>, params: IblParams) {
+        self.params.set_data(queue, params);
+        shader.run_once(vec![&cube.binding, &self.params.binding, &self.target.binding], [params.size.div_ceil(8), params.size.div_ceil(8), 1], device, queue);
+    }
+}
+
+// FNV-1a over the Rgba16Float texels of the first mip of the faces, read out like `IblMaps::to_bytes` does it
+pub fn environment_hash(device: &Device, queue: &Queue, environment: &Texture) -> anyhow::Result<u64> {
+    let size = environment.size.width;
+    let mut passes = FacePasses::new(device, size);
+    let cube = UniformBinding::new(device, "IBL Read Environment", environment.clone().with_sampler(device, wgpu::FilterMode::Nearest, wgpu::AddressMode::ClampToEdge), None);
+    let shader = passes.shader(device, include_str!("ibl_read.wgsl"), &cube);
+    let mut hash = 0xcbf29ce484222325u64;
+    for face in 0..6 {
+        passes.run(device, queue, &shader, &cube, IblParams { roughness: 0.0, size, samples: 0, face });
+        for byte in read_face(device, queue, &passes.scratch.texture, size)? {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    Ok(hash)
+}
+
+// 6 layers make a cubemap, 1 a plain 2D texture
+fn ibl_texture(device: &Device, label: &str, size: u32, layers: u32, mips: u32) -> Texture {
+    let extent = wgpu::Extent3d { width: size, height: size, depth_or_array_layers: layers };
+    let texture = create_texture(device, &wgpu::TextureDescriptor {
+        label: Some(label),
+        size: extent,
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: IBL_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[IBL_FORMAT],
+    });
+    let dimensions = if layers == 6 { TextureViewDimension::Cube } else { TextureViewDimension::D2 };
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(dimensions),
+        ..Default::default()
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::MipmapFilterMode::Linear,
+        ..Default::default()
+    });
+    Texture { texture, view, sampler, size: extent, format: IBL_FORMAT, dimensions, sample_count: 1 }
+}
+
+// the top left `size` texels of the first mip and layer, rows are copied out padded to 256 bytes and packed again
+fn read_face(device: &Device, queue: &Queue, texture: &wgpu::Texture, size: u32) -> anyhow::Result<Vec<u8>> {
+    let row = size * TEXEL_BYTES;
+    let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("IBL Read Buffer"),
+        size: (padded_row * size) as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo { buffer: &buffer, layout: wgpu::TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(padded_row), rows_per_image: Some(size) } },
+        wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+    );
+    queue.submit([encoder.finish()]);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    receiver.recv()??;
+    let padded = buffer.slice(..).get_mapped_range()?.to_vec();
+    buffer.unmap();
+    Ok(padded.chunks(padded_row as usize).flat_map(|chunk| &chunk[..row as usize]).copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_support, texture::texture_allocations};
+
+    use super::{IblConfig, IblHeader, IblMaps, IBL_MAGIC, IBL_VERSION};
+
+    fn cache(config: IblConfig, texel_bytes: usize) -> Vec<u8> {
+        let header = IblHeader {
+            magic: IBL_MAGIC,
+            version: IBL_VERSION,
+            irradiance_size: config.irradiance_size,
+            specular_size: config.specular_size,
+            specular_mips: config.specular_mips,
+            brdf_size: config.brdf_size,
+            samples: config.samples,
+            padding: 0,
+            environment_hash: 0,
+        };
+        [bytemuck::bytes_of(&header).to_vec(), vec![0; texel_bytes]].concat()
+    }
+
+    #[test]
+    fn rejects_bad_headers_before_allocating() {
+        let Some((device, queue)) = test_support::device() else { return; };
+        let _lock = test_support::GPU_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let config = IblConfig { irradiance_size: 2, specular_size: 4, specular_mips: 3, brdf_size: 2, samples: 1 };
+        let length = config.byte_len().unwrap();
+        let before = texture_allocations();
+        for bytes in [
+            cache(IblConfig { specular_mips: 0, ..config }, length),
+            cache(IblConfig { specular_mips: 4, ..config }, length),
+            cache(IblConfig { specular_mips: u32::MAX, ..config }, length),
+            cache(IblConfig { irradiance_size: 0, ..config }, length),
+            cache(IblConfig { brdf_size: u32::MAX, ..config }, length),
+            cache(config, length - 1),
+            cache(config, length + 1),
+        ] {
+            assert!(IblMaps::from_bytes(&device, &queue, &bytes).is_err());
+        }
+        assert_eq!(texture_allocations() - before, 0);
+        let maps = IblMaps::from_bytes(&device, &queue, &cache(config, length)).unwrap();
+        assert_eq!(maps.config, config);
+    }
+}
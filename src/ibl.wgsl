@@ -0,0 +1,13 @@
+// Ambient light from the maps of `IblMaps`, the split sum approximation for the specular part.
+// `specular_mips` is the number of mip levels of the specular map, roughness 1 uses the last one
+fn ibl_ambient(irradiance_map: texture_cube<f32>, specular_map: texture_cube<f32>, brdf_lut: texture_2d<f32>, ibl_sampler: sampler, normal: vec3<f32>, view_dir: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32, occlusion: f32, specular_mips: f32) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let f0 = mix(vec3(0.04), albedo, metallic);
+    let fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    let diffuse = textureSampleLevel(irradiance_map, ibl_sampler, normal, 0.0).rgb * albedo * (1.0 - fresnel) * (1.0 - metallic);
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(specular_map, ibl_sampler, reflected, roughness * (specular_mips - 1.0)).rgb;
+    let brdf = textureSampleLevel(brdf_lut, ibl_sampler, vec2(n_dot_v, roughness), 0.0).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+    return (diffuse + specular) * occlusion;
+}
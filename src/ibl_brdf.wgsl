@@ -0,0 +1,40 @@
+#include "buildins/ibl_common.wgsl"
+
+params: $0;
+target_texture: $1,0;
+
+fn geometry_schlick_ggx(n_dot: f32, roughness: f32) -> f32 {
+    // k for image based lighting, not the one for analytic lights
+    let k = roughness * roughness / 2.0;
+    return n_dot / (n_dot * (1.0 - k) + k);
+}
+
+// Scale and bias to the Fresnel term for n dot v along x and roughness along y
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(params.size);
+    let n_dot_v = uv.x;
+    let roughness = uv.y;
+    let view = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3(0.0, 0.0, 1.0);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.samples; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, params.samples), normal, roughness);
+        let light = normalize(2.0 * dot(view, half_vector) * half_vector - view);
+        let n_dot_l = max(light.z, 0.0);
+        let n_dot_h = max(half_vector.z, 0.0);
+        let v_dot_h = max(dot(view, half_vector), 0.0);
+        if n_dot_l > 0.0 {
+            let geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    textureStore(target_texture, id.xy, vec4(scale, bias, 0.0, 1.0) / vec4(f32(params.samples), f32(params.samples), 1.0, 1.0));
+}
@@ -0,0 +1,55 @@
+const PI: f32 = 3.14159265;
+
+struct IblParams {
+    roughness: f32,
+    size: u32,
+    samples: u32,
+    // the cube face being written, in `cube_direction` order
+    face: u32,
+};
+
+// face in the order +X, -X, +Y, -Y, +Z, -Z and uv from the top left of the face
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3(1.0, -t, -s)); }
+        case 1u: { return normalize(vec3(-1.0, -t, s)); }
+        case 2u: { return normalize(vec3(s, 1.0, t)); }
+        case 3u: { return normalize(vec3(s, -1.0, -t)); }
+        case 4u: { return normalize(vec3(s, -t, 1.0)); }
+        default: { return normalize(vec3(-s, -t, -1.0)); }
+    }
+}
+
+fn radical_inverse(index: u32) -> f32 {
+    var bits = (index << 16u) | (index >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    return vec2(f32(index) / f32(count), radical_inverse(index));
+}
+
+fn tangent_to_world(sample: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var up = vec3(0.0, 0.0, 1.0);
+    if abs(normal.z) > 0.999 {
+        up = vec3(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * sample.x + bitangent * sample.y + normal * sample.z);
+}
+
+// a half vector around the normal, distributed like the GGX lobe
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), normal);
+}
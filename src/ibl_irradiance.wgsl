@@ -0,0 +1,25 @@
+#include "buildins/ibl_common.wgsl"
+
+environment: $0,0;
+environment_sampler: $0,1;
+params: $1;
+target_texture: $2,0;
+
+// Cosine weighted integral of the environment over the hemisphere around every texel direction
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let normal = cube_direction(params.face, (vec2<f32>(id.xy) + 0.5) / f32(params.size));
+    var irradiance = vec3(0.0);
+    for (var i = 0u; i < params.samples; i++) {
+        let xi = hammersley(i, params.samples);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let direction = tangent_to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), normal);
+        irradiance += textureSampleLevel(environment, environment_sampler, direction, 0.0).rgb;
+    }
+    textureStore(target_texture, id.xy, vec4(irradiance / f32(params.samples), 1.0));
+}
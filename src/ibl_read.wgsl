@@ -0,0 +1,16 @@
+#include "buildins/ibl_common.wgsl"
+
+environment: $0,0;
+environment_sampler: $0,1;
+params: $1;
+target_texture: $2,0;
+
+// Copies one face and mip of a cubemap out through texel center samples, `roughness` holds the mip level
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let direction = cube_direction(params.face, (vec2<f32>(id.xy) + 0.5) / f32(params.size));
+    textureStore(target_texture, id.xy, textureSampleLevel(environment, environment_sampler, direction, params.roughness));
+}
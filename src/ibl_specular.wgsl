@@ -0,0 +1,31 @@
+#include "buildins/ibl_common.wgsl"
+
+environment: $0,0;
+environment_sampler: $0,1;
+params: $1;
+target_texture: $2,0;
+
+// The environment convolved with the GGX lobe for one roughness, assuming the view is along the normal
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let normal = cube_direction(params.face, (vec2<f32>(id.xy) + 0.5) / f32(params.size));
+    if params.roughness == 0.0 {
+        textureStore(target_texture, id.xy, vec4(textureSampleLevel(environment, environment_sampler, normal, 0.0).rgb, 1.0));
+        return;
+    }
+    var color = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.samples; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, params.samples), normal, params.roughness);
+        let light = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        let n_dot_l = dot(normal, light);
+        if n_dot_l > 0.0 {
+            color += textureSampleLevel(environment, environment_sampler, light, 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    textureStore(target_texture, id.xy, vec4(color / max(weight, 0.0001), 1.0));
+}
@@ -1,22 +1,47 @@
-use crate::{binding::Descriptor, model::ToRaw};
-use bytemuck::bytes_of;
-use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+use std::{any::TypeId, sync::Mutex};
 
+use crate::{binding::Descriptor, model::ToRaw, InstanceTrait};
+use bytemuck::{bytes_of, Pod};
+use cgmath::{Deg, Matrix3, Matrix4, Quaternion, Rotation3, Vector3};
+
+// `E` is extra per instance data placed after the matrices, and with `NORMAL_MATRIX` the inverse transpose of the
+// model matrix is passed along for non-uniform scale
 #[derive(Clone)]
-pub struct Instance {
+pub struct Instance<E: InstanceExtra = (), const NORMAL_MATRIX: bool = false> {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    pub extra: E,
 }
 
-impl Instance {
+pub type NormalInstance<E = ()> = Instance<E, true>;
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> Instance<E, NORMAL_MATRIX> {
     pub fn raw(&self) -> InstanceRaw {
-        InstanceRaw {model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into() }
+        InstanceRaw { model: self.model_matrix().into() }
+    }
+
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position) * Matrix4::from(self.rotation) * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        let inverse = |scale: f32| if scale == 0.0 { 0.0 } else { 1.0 / scale };
+        // (R * S)^-1^T = R * S^-1 since R is orthogonal
+        let rotation = Matrix3::from(self.rotation);
+        Matrix3::from_cols(rotation.x * inverse(self.scale.x), rotation.y * inverse(self.scale.y), rotation.z * inverse(self.scale.z))
     }
 }
 
-impl Default for Instance {
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> Default for Instance<E, NORMAL_MATRIX> {
     fn default() -> Self {
-        Self { position: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)) }
+        Self { position: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)), scale: Vector3::new(1.0, 1.0, 1.0), extra: E::zeroed() }
+    }
+}
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> InstanceTrait for Instance<E, NORMAL_MATRIX> {
+    fn instance_transform(&self) -> Matrix4<f32> {
+        self.model_matrix()
     }
 }
 
@@ -26,48 +51,122 @@ pub struct InstanceRaw {
     model: [[f32; 4]; 4],
 }
 
-impl ToRaw for Instance {
+// Extra per instance data. The bytes have to match the WGSL fields in a storage buffer, where a vec3 is aligned like a vec4
+pub trait InstanceExtra: Pod {
+    // formats of the fields in order, they get the shader locations after the matrices
+    fn vertex_formats() -> Vec<wgpu::VertexFormat>;
+    // the same fields as WGSL struct members, e.g. "color: vec4f,"
+    fn wgsl_fields() -> String;
+}
+
+impl InstanceExtra for () {
+    fn vertex_formats() -> Vec<wgpu::VertexFormat> {
+        vec![]
+    }
+
+    fn wgsl_fields() -> String {
+        String::new()
+    }
+}
+
+impl InstanceExtra for [f32; 4] {
+    fn vertex_formats() -> Vec<wgpu::VertexFormat> {
+        vec![wgpu::VertexFormat::Float32x4]
+    }
+
+    fn wgsl_fields() -> String {
+        "extra: vec4f,".into()
+    }
+}
+
+impl InstanceExtra for u32 {
+    fn vertex_formats() -> Vec<wgpu::VertexFormat> {
+        vec![wgpu::VertexFormat::Uint32]
+    }
+
+    fn wgsl_fields() -> String {
+        "extra: u32,".into()
+    }
+}
+
+// An instance type that can go through `CullingCompute`, see `CullingCompute::for_instance`
+pub trait InstanceType: ToRaw + Descriptor {
+    // a WGSL struct named `Instance` with the same layout as `to_raw`
+    fn wgsl_struct() -> String;
+    // the field of `wgsl_struct` holding the model matrix
+    fn wgsl_matrix_field() -> &'static str;
+}
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> Instance<E, NORMAL_MATRIX> {
+    // 64 bytes of model matrix, 48 of normal matrix columns padded to vec4, then the extra data,
+    // rounded up to the 16 byte alignment of the WGSL struct
+    fn stride() -> usize {
+        (64 + if NORMAL_MATRIX { 48 } else { 0 } + std::mem::size_of::<E>()).next_multiple_of(16)
+    }
+}
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> ToRaw for Instance<E, NORMAL_MATRIX> {
     fn to_raw(&self) -> Vec<u8> {
-        let raw = InstanceRaw {model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into() };
-        bytes_of(&raw).to_vec()
+        let mut raw = bytes_of(&self.raw()).to_vec();
+        if NORMAL_MATRIX {
+            let normal = self.normal_matrix();
+            for column in [normal.x, normal.y, normal.z] {
+                raw.extend_from_slice(bytes_of(&[column.x, column.y, column.z, 0.0]));
+            }
+        }
+        raw.extend_from_slice(bytes_of(&self.extra));
+        raw.resize(Self::stride(), 0);
+        raw
     }
 }
 
-impl Descriptor for Instance {
+// vertex attributes have to outlive the layout, so they're built once per instance type and leaked
+static INSTANCE_ATTRIBUTES: Mutex<Vec<(TypeId, &'static [wgpu::VertexAttribute])>> = Mutex::new(vec![]);
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> Descriptor for Instance<E, NORMAL_MATRIX> {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
+        let mut cache = INSTANCE_ATTRIBUTES.lock().unwrap();
+        let attributes = match cache.iter().find(|(id, _)| *id == TypeId::of::<Self>()) {
+            Some((_, attributes)) => *attributes,
+            None => {
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s, starting at 5 to leave room for the vertex attributes.
+                // The normal matrix takes 3 more and the extra fields follow, up to location 13. Vertices use 0-4 and 14-15
+                // (`SkinnedVertex` joints and weights), which stays within the 16 attributes wgpu guarantees
+                let mut formats = vec![wgpu::VertexFormat::Float32x4; if NORMAL_MATRIX { 7 } else { 4 }];
+                formats.extend(E::vertex_formats());
+                assert!(formats.len() <= 9, "instance attributes would run into the vertex locations from 14 on");
+                let mut offset = 0;
+                let attributes: &'static [wgpu::VertexAttribute] = formats.iter().enumerate().map(|(i, format)| {
+                    let attribute = wgpu::VertexAttribute { offset, shader_location: 5 + i as u32, format: *format };
+                    offset += format.size();
+                    attribute
+                }).collect::<Vec<_>>().leak();
+                cache.push((TypeId::of::<Self>(), attributes));
+                attributes
+            }
+        };
         wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            array_stride: Self::stride() as wgpu::BufferAddress,
             // We need to switch from using a step mode of Vertex to Instance
             // This means that our shaders will only change to use the next
             // instance when the shader starts processing a new instance
             step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We'll have to reassemble the mat4 in the shader.
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials, we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5, not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+            attributes,
+        }
+    }
+}
+
+impl<E: InstanceExtra, const NORMAL_MATRIX: bool> InstanceType for Instance<E, NORMAL_MATRIX> {
+    fn wgsl_struct() -> String {
+        let mut fields = vec!["model: mat4x4f,".to_string()];
+        if NORMAL_MATRIX {
+            fields.push("normal: mat3x3f,".into());
         }
+        fields.push(E::wgsl_fields());
+        format!("struct Instance {{\n{}\n}};", fields.iter().filter(|field| !field.is_empty()).map(|field| format!("    {field}")).collect::<Vec<_>>().join("\n"))
     }
-}
\ No newline at end of file
+
+    fn wgsl_matrix_field() -> &'static str {
+        "model"
+    }
+}
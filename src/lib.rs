@@ -11,6 +11,26 @@ pub mod billboard;
 pub mod instance;
 pub mod surface_context;
 pub mod culling;
+pub mod texture_atlas;
+pub mod render_target;
+pub mod post_process;
+pub mod post_process_effects;
+pub mod gltf_loader;
+pub mod animation;
+pub mod mesh_processing;
+pub mod procedural_mesh;
+pub mod baked_mesh;
+pub mod scene;
+pub mod lighting;
+pub mod shadow;
+pub mod deferred;
+pub mod skybox;
+pub mod ibl;
+pub mod render_queue;
+pub mod oit;
+pub mod particles;
+#[cfg(test)]
+mod test_support;
 
 pub trait VertexTrait {
     fn pos(&self) -> cgmath::Vector3<f32>;
@@ -0,0 +1,37 @@
+// The light and shadow structs, pulled into a shader with `#include "buildins/light_types.wgsl"`. lighting.wgsl and
+// shadows.wgsl include it already
+
+// see lighting.rs, kind 0 = directional, 1 = point, 2 = spot
+struct Light {
+    position: vec3f,
+    kind: u32,
+    direction: vec3f,
+    // 0 means no cutoff
+    range: f32,
+    color: vec3f,
+    intensity: f32,
+    inner_cos: f32,
+    outer_cos: f32,
+    shadow_layer: u32,
+    shadow_layers: u32,
+};
+
+struct Lights {
+    ambient: vec3f,
+    count: u32,
+    lights: array<Light>,
+};
+
+// see shadow.rs, one per layer of the shadow map
+struct ShadowCascade {
+    view_proj: mat4x4f,
+    // distance from the camera where the next cascade takes over
+    split: f32,
+    bias: f32,
+    normal_bias: f32,
+    texel_size: f32,
+};
+
+struct Shadows {
+    cascades: array<ShadowCascade, 16>,
+};
\ No newline at end of file
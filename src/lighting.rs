@@ -0,0 +1,173 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{binding::{Binding, Resource, WgslType}, shader::ShaderType};
+
+// Angles are in radians and directions point where the light shines. `range` is where point and spot lights fade out,
+// 0 means they only fall off with the distance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    Directional { direction: Vector3<f32>, color: [f32; 3], intensity: f32 },
+    Point { position: Vector3<f32>, color: [f32; 3], intensity: f32, range: f32 },
+    Spot { position: Vector3<f32>, direction: Vector3<f32>, color: [f32; 3], intensity: f32, range: f32, inner_angle: f32, outer_angle: f32 },
+}
+
+impl Light {
+    pub fn to_raw(&self) -> LightRaw {
+        match *self {
+            Light::Directional { direction, color, intensity } => LightRaw {
+                kind: LightRaw::DIRECTIONAL,
+                direction: direction.normalize().into(),
+                color,
+                intensity,
+                ..Default::default()
+            },
+            Light::Point { position, color, intensity, range } => LightRaw {
+                position: position.into(),
+                kind: LightRaw::POINT,
+                range,
+                color,
+                intensity,
+                ..Default::default()
+            },
+            Light::Spot { position, direction, color, intensity, range, inner_angle, outer_angle } => LightRaw {
+                position: position.into(),
+                kind: LightRaw::SPOT,
+                direction: direction.normalize().into(),
+                range,
+                color,
+                intensity,
+                // the inner cone can't be wider than the outer one
+                inner_cos: inner_angle.min(outer_angle).cos(),
+                outer_cos: outer_angle.cos(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LightRaw {
+    pub position: [f32; 3],
+    pub kind: u32,
+    pub direction: [f32; 3],
+    pub range: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub inner_cos: f32,
+    pub outer_cos: f32,
+    // the layers of the `ShadowMap` this light uses, none if `shadow_layers` is 0
+    pub shadow_layer: u32,
+    pub shadow_layers: u32,
+}
+
+impl LightRaw {
+    pub const DIRECTIONAL: u32 = 0;
+    pub const POINT: u32 = 1;
+    pub const SPOT: u32 = 2;
+}
+
+impl WgslType for LightRaw {
+    fn wgsl_name() -> String {
+        "Light".into()
+    }
+}
+
+// Up to `N` lights in a storage buffer that shows up in shaders as `Lights`, the buffer always has room for all `N`
+// so changes can go through `UniformBinding::set_data`. See lighting.wgsl for the shader side, or include
+// light_types.wgsl for just the structs
+#[derive(Clone, Debug)]
+pub struct LightSet<const N: usize> {
+    pub ambient: [f32; 3],
+    lights: Vec<Light>,
+    // [first layer, layer count] for every light
+    shadows: Vec<[u32; 2]>,
+}
+
+impl<const N: usize> Default for LightSet<N> {
+    fn default() -> Self {
+        Self { ambient: [0.03; 3], lights: vec![], shadows: vec![] }
+    }
+}
+
+impl<const N: usize> LightSet<N> {
+    pub fn new(ambient: [f32; 3]) -> Self {
+        Self { ambient, lights: vec![], shadows: vec![] }
+    }
+
+    pub fn push(&mut self, light: Light) -> anyhow::Result<usize> {
+        if self.lights.len() >= N {
+            anyhow::bail!("light set is full, it holds at most {N} lights");
+        }
+        self.lights.push(light);
+        self.shadows.push([0, 0]);
+        Ok(self.lights.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Light> {
+        (index < self.lights.len()).then(|| {
+            self.shadows.remove(index);
+            self.lights.remove(index)
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.shadows.clear();
+    }
+
+    // Makes the light sample `layers` layers of the shadow map starting at `first_layer`, more than one means cascades.
+    // 0 layers turns the shadow off
+    pub fn set_shadow(&mut self, index: usize, first_layer: u32, layers: u32) {
+        if let Some(shadow) = self.shadows.get_mut(index) {
+            *shadow = [first_layer, layers];
+        }
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        &mut self.lights
+    }
+
+    // ambient and count as a 16 byte header, then N lights
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bytes_of(&self.ambient).to_vec();
+        bytes.extend_from_slice(bytes_of(&(self.lights.len() as u32)));
+        for i in 0..N.max(1) {
+            let raw = self.lights.get(i).map(|light| LightRaw { shadow_layer: self.shadows[i][0], shadow_layers: self.shadows[i][1], ..light.to_raw() });
+            bytes.extend_from_slice(bytes_of(&raw.unwrap_or_default()));
+        }
+        bytes
+    }
+}
+
+impl<const N: usize> Binding for LightSet<N> {
+    type LayoutConfig = ();
+    fn layout(_config: Self::LayoutConfig, ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: ty.unwrap_or(wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, has_dynamic_offset: false, min_binding_size: None })
+            }
+        ]
+    }
+
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        vec![Resource::Simple(self.to_bytes())]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["<storage, read>".into()],
+            wgsl_types: vec!["Lights".into()],
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Shared light evaluation, pulled into a shader with `#include "buildins/lighting.wgsl"`.
+// The `LightSet` binding shows up as `Lights` and is looped over with
+// `for (var i = 0u; i < lights.count; i++) { color += pbr_light(lights.lights[i], ...); }`
+// `position` is in world space, `normal` and `view_dir` (surface to camera) are normalized.
+#include "buildins/light_types.wgsl"
+
+const PI: f32 = 3.14159265359;
+
+struct LightSample {
+    // from the surface towards the light
+    direction: vec3f,
+    radiance: vec3f,
+};
+
+fn light_attenuation(distance: f32, range: f32) -> f32 {
+    let falloff = 1.0 / (distance * distance + 1.0);
+    if range <= 0.0 {
+        return falloff;
+    }
+    // fades to 0 at the range instead of cutting off
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return falloff * window * window;
+}
+
+fn sample_light(light: Light, position: vec3f) -> LightSample {
+    if light.kind == 0u {
+        return LightSample(normalize(-light.direction), light.color * light.intensity);
+    }
+    let offset = light.position - position;
+    let distance = length(offset);
+    let direction = offset / max(distance, 0.0001);
+    var attenuation = light_attenuation(distance, light.range);
+    if light.kind == 2u {
+        let cos_angle = dot(-direction, normalize(light.direction));
+        attenuation *= smoothstep(light.outer_cos, light.inner_cos, cos_angle);
+    }
+    return LightSample(direction, light.color * light.intensity * attenuation);
+}
+
+fn blinn_phong_light(light: Light, position: vec3f, normal: vec3f, view_dir: vec3f, diffuse: vec3f, specular: vec3f, shininess: f32) -> vec3f {
+    let sample = sample_light(light, position);
+    let n_dot_l = max(dot(normal, sample.direction), 0.0);
+    if n_dot_l <= 0.0 {
+        return vec3f(0.0);
+    }
+    let half_dir = normalize(sample.direction + view_dir);
+    let specular_amount = pow(max(dot(normal, half_dir), 0.0), max(shininess, 1.0));
+    return (diffuse * n_dot_l + specular * specular_amount) * sample.radiance;
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3f) -> vec3f {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance with GGX, metallic/roughness like glTF
+fn pbr_light(light: Light, position: vec3f, normal: vec3f, view_dir: vec3f, albedo: vec3f, metallic: f32, roughness: f32) -> vec3f {
+    let sample = sample_light(light, position);
+    let n_dot_l = max(dot(normal, sample.direction), 0.0);
+    if n_dot_l <= 0.0 {
+        return vec3f(0.0);
+    }
+    let roughness_clamped = clamp(roughness, 0.04, 1.0);
+    let half_dir = normalize(sample.direction + view_dir);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let f0 = mix(vec3f(0.04), albedo, metallic);
+    let fresnel = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+    let specular = distribution_ggx(max(dot(normal, half_dir), 0.0), roughness_clamped) * geometry_smith(n_dot_v, n_dot_l, roughness_clamped) * fresnel / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * sample.radiance * n_dot_l;
+}
+
+// The ambient term of the light set for a diffuse color, with an occlusion factor
+fn ambient_light(ambient: vec3f, albedo: vec3f, occlusion: f32) -> vec3f {
+    return ambient * albedo * occlusion;
+}
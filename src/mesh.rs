@@ -1,15 +1,303 @@
-use std::{io::{BufReader, Cursor}, ops::Range, path::Path};
+use std::{collections::HashMap, ops::Range, path::Path};
 
-use bytemuck::bytes_of;
+use bytemuck::{bytes_of, Pod, Zeroable};
 use cgmath::{Matrix4, SquareMatrix};
-use wgpu::{util::DeviceExt, Buffer, RenderPass};
+use wgpu::{util::DeviceExt, BindGroupLayout, Buffer, Device, Queue, RenderPass};
 
-use crate::{binding::{Descriptor, UniformBinding}, camera::Camera, culling::{culled, CullingCompute}, model::{calculate_bounding_box, Model, Render, ToRaw}, resource_loader::load_resource, surface_context::SurfaceCtx, texture::Texture, VertexTrait};
+use crate::{baked_mesh::BakedMesh, binding::{Descriptor, UniformBinding, WgslType}, camera::Camera, culling::{culled, CullingCompute, AABB}, mesh_processing::{MeshProcessingOptions, OptimizationReport}, model::{Model, Render, ToRaw, INDEX_USAGE, VERTEX_USAGE}, resource_loader::load_resource, shader::ShaderType, surface_context::SurfaceCtx, texture::Texture, VertexTrait};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MaterialMap {
+    Diffuse,
+    Ambient,
+    Specular,
+    Shininess,
+    Normal,
+    Dissolve,
+    Emissive,
+    MetallicRoughness,
+    Occlusion,
+}
+
+impl MaterialMap {
+    pub const ALL: [MaterialMap; 9] = [
+        MaterialMap::Diffuse,
+        MaterialMap::Ambient,
+        MaterialMap::Specular,
+        MaterialMap::Shininess,
+        MaterialMap::Normal,
+        MaterialMap::Dissolve,
+        MaterialMap::Emissive,
+        MaterialMap::MetallicRoughness,
+        MaterialMap::Occlusion,
+    ];
+
+    // color maps are loaded as sRGB, the rest as linear data
+    pub fn is_color(&self) -> bool {
+        matches!(self, MaterialMap::Diffuse | MaterialMap::Ambient | MaterialMap::Specular | MaterialMap::Emissive)
+    }
+
+    pub fn bit(&self) -> u32 {
+        1 << *self as u32
+    }
+}
+
+// All MTL fields, bound as a uniform after the maps when the layout asks for it
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, PartialEq)]
+pub struct MaterialUniform {
+    pub ambient: [f32; 3],
+    pub shininess: f32,
+    pub diffuse: [f32; 3],
+    pub dissolve: f32,
+    pub specular: [f32; 3],
+    pub optical_density: f32,
+    pub emissive: [f32; 3],
+    pub illumination_model: u32,
+    // `MaterialMap::bit` is set for every map that came from the file instead of a default texture
+    pub loaded_maps: u32,
+    pub padding: [u32; 3],
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            ambient: [0.0; 3],
+            shininess: 32.0,
+            diffuse: [1.0; 3],
+            dissolve: 1.0,
+            specular: [0.0; 3],
+            optical_density: 1.0,
+            emissive: [0.0; 3],
+            illumination_model: 2,
+            loaded_maps: 0,
+            padding: [0; 3],
+        }
+    }
+}
+
+impl WgslType for MaterialUniform {
+    fn wgsl_name() -> String {
+        "MaterialUniform".into()
+    }
+}
+
+impl From<&tobj::Material> for MaterialUniform {
+    fn from(material: &tobj::Material) -> Self {
+        let default = Self::default();
+        Self {
+            ambient: material.ambient.unwrap_or(default.ambient),
+            shininess: material.shininess.unwrap_or(default.shininess),
+            diffuse: material.diffuse.unwrap_or(default.diffuse),
+            dissolve: material.dissolve.unwrap_or(default.dissolve),
+            specular: material.specular.unwrap_or(default.specular),
+            optical_density: material.optical_density.unwrap_or(default.optical_density),
+            emissive: material.emissive.unwrap_or(default.emissive),
+            illumination_model: material.illumination_model.map(|it| it as u32).unwrap_or(default.illumination_model),
+            ..default
+        }
+    }
+}
+
+// Describes the material bind group a shader expects: a texture at binding 2i and its sampler at 2i + 1 for every map,
+// followed by the `MaterialUniform` if `uniform` is set
+pub struct MaterialLayout {
+    pub maps: Vec<MaterialMap>,
+    pub uniform: bool,
+    pub layout: BindGroupLayout,
+}
+
+impl MaterialLayout {
+    pub fn new(device: &Device, maps: Vec<MaterialMap>, uniform: bool) -> Self {
+        let mut entries = vec![];
+        for i in 0..maps.len() as u32 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        if uniform {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: maps.len() as u32 * 2,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                count: None,
+            });
+        }
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("Material Layout"),
+        });
+        Self { maps, uniform, layout }
+    }
+
+    // Just the diffuse texture and its sampler at bindings 0 and 1
+    pub fn diffuse(device: &Device) -> Self {
+        Self::new(device, vec![MaterialMap::Diffuse], false)
+    }
+
+    pub fn shader_type(&self) -> ShaderType {
+        let mut shader_type = ShaderType { var_types: vec![], wgsl_types: vec![] };
+        for _ in &self.maps {
+            shader_type.var_types.extend(["".into(), "".into()]);
+            shader_type.wgsl_types.extend(["texture_2d<f32>".into(), "sampler".into()]);
+        }
+        if self.uniform {
+            shader_type.var_types.push("<uniform>".into());
+            shader_type.wgsl_types.push(MaterialUniform::wgsl_name());
+        }
+        shader_type
+    }
+}
+
+// Stand ins for maps a material doesn't have, created once per loaded model
+pub struct DefaultTextures {
+    pub white: Texture,
+    pub flat_normal: Texture,
+}
+
+impl DefaultTextures {
+    pub fn new(device: &Device, queue: &Queue) -> anyhow::Result<Self> {
+        Ok(Self {
+            white: Texture::from_color(device, queue, [255; 4], wgpu::TextureFormat::Rgba8UnormSrgb)?,
+            flat_normal: Texture::from_color(device, queue, [128, 128, 255, 255], wgpu::TextureFormat::Rgba8Unorm)?,
+        })
+    }
+
+    pub fn get(&self, map: MaterialMap) -> &Texture {
+        match map {
+            MaterialMap::Normal => &self.flat_normal,
+            _ => &self.white,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Material {
     pub name: String,
+    // the diffuse map, or the default white texture
     pub diffuse_texture: crate::texture::Texture,
+    // only the maps that were actually loaded
+    pub textures: HashMap<MaterialMap, Texture>,
+    pub values: MaterialUniform,
+    pub uniform_buffer: Buffer,
     pub bind_group: wgpu::BindGroup,
+    // only set for materials loaded from glTF
+    pub pbr: Option<PbrMaterial>,
+}
+
+impl Material {
+    pub fn new(device: &Device, name: String, mut values: MaterialUniform, textures: HashMap<MaterialMap, Texture>, layout: &MaterialLayout, defaults: &DefaultTextures) -> Self {
+        values.loaded_maps = textures.keys().fold(0, |bits, map| bits | map.bit());
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name} Material Buffer")),
+            contents: bytes_of(&values),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let map_textures = layout.maps.iter().map(|map| textures.get(map).unwrap_or(defaults.get(*map))).collect::<Vec<_>>();
+        let mut entries = vec![];
+        for (i, texture) in map_textures.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        if layout.uniform {
+            entries.push(wgpu::BindGroupEntry {
+                binding: layout.maps.len() as u32 * 2,
+                resource: uniform_buffer.as_entire_binding(),
+            });
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout.layout,
+            entries: &entries,
+            label: Some(&format!("{name} Material Binding")),
+        });
+        Self {
+            name,
+            diffuse_texture: textures.get(&MaterialMap::Diffuse).unwrap_or(&defaults.white).clone(),
+            textures,
+            values,
+            uniform_buffer,
+            bind_group,
+            pbr: None,
+        }
+    }
+
+    pub fn texture(&self, map: MaterialMap) -> Option<&Texture> {
+        self.textures.get(&map)
+    }
+
+    pub fn set_values(&mut self, queue: &Queue, values: MaterialUniform) {
+        self.values = MaterialUniform { loaded_maps: self.values.loaded_maps, ..values };
+        queue.write_buffer(&self.uniform_buffer, 0, bytes_of(&self.values));
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PbrFactors {
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_cutoff: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self { base_color: [1.0; 4], emissive: [0.0; 3], metallic: 1.0, roughness: 1.0, normal_scale: 1.0, occlusion_strength: 1.0, alpha_cutoff: 0.5 }
+    }
+}
+
+impl WgslType for PbrFactors {
+    fn wgsl_name() -> String {
+        "PbrFactors".into()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+#[derive(Clone)]
+pub struct PbrTexture {
+    pub texture: Texture,
+    // which uv set of the vertex the texture is sampled with
+    pub tex_coord: u32,
+}
+
+#[derive(Clone)]
+pub struct PbrMaterial {
+    pub factors: PbrFactors,
+    pub base_color_texture: Option<PbrTexture>,
+    pub metallic_roughness_texture: Option<PbrTexture>,
+    pub normal_texture: Option<PbrTexture>,
+    pub occlusion_texture: Option<PbrTexture>,
+    pub emissive_texture: Option<PbrTexture>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
 }
 
 // pub struct Mesh {
@@ -22,175 +310,196 @@ pub struct Material {
 
 pub struct MeshModel {
     pub models: Vec<Model>,
+    // material index of every model
     pub model_materials: Vec<usize>,
     pub materials: Vec<Material>,
     pub enable_material_binding: bool,
+    // the bind group index the materials get bound to
+    pub material_group: u32,
+    // one per model when loaded with `MeshProcessingOptions::optimize`, empty otherwise
+    pub optimization: Vec<OptimizationReport>,
 }
 
 impl Render for MeshModel {
     fn render<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) {
-        // for (i, material) in self.materials.iter().enumerate() {
-        //     render_pass.set_bind_group(i as u32, &material.bind_group, &[]);
-        // }
-        for (i, mesh) in self.models.iter().enumerate() {
-            if self.enable_material_binding {
-                if let Some(material) = self.materials.get(i) {
-                    render_pass.set_bind_group(0, &material.bind_group, &[]);
-                }
-            }
-            mesh.render(render_pass);
-            // render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            // render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            // render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        let mut bound = None;
+        for i in self.draw_order() {
+            self.bind_material(render_pass, i, &mut bound);
+            self.models[i].render(render_pass);
         }
     }
 
     fn render_culled_transformed<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instance_transform: Option<cgmath::Matrix4<f32>>, camera: &crate::camera::Camera) {
-        for (i, mesh) in self.models.iter().enumerate() {
+        let mut bound = None;
+        for i in self.draw_order() {
+            let mesh = &self.models[i];
             if !culled(mesh, instance_transform.unwrap_or(Matrix4::identity()), camera) {
-                if self.enable_material_binding {
-                    if let Some(material) = self.materials.get(i) {
-                        render_pass.set_bind_group(0, &material.bind_group, &[]);
-                    }
-                }
+                self.bind_material(render_pass, i, &mut bound);
                 mesh.render(render_pass);
-                // render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                // render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                // render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
             }
         }
     }
 
     fn render_culled<'a: 'b, 'b>(&'a self, camera: &UniformBinding<Camera>, render_pass: &mut RenderPass<'b>, culling: &mut CullingCompute, surface_ctx: &dyn SurfaceCtx) {
-        for mesh in &self.models {
+        let mut bound = None;
+        for i in self.draw_order() {
+            let mesh = &self.models[i];
             if let Some(instance_buffer) = &mesh.instance_buffer {
-                let (culled_instances, num_instances) = culling.run(&instance_buffer, mesh.num_instances, &mesh.bounding_box, camera, surface_ctx.device(), surface_ctx.queue());
-                println!("{num_instances}");
-                self.render_instances(render_pass, &culled_instances, 0..num_instances);
-            } else {
-                self.render_culled_transformed(render_pass, None, &camera.value);
+                let (culled_instances, num_instances) = culling.run(instance_buffer, mesh.num_instances, &mesh.bounding_box, camera, surface_ctx.device(), surface_ctx.queue());
+                self.bind_material(render_pass, i, &mut bound);
+                mesh.render_instances(render_pass, &culled_instances, 0..num_instances);
+            } else if !culled(mesh, Matrix4::identity(), &camera.value) {
+                self.bind_material(render_pass, i, &mut bound);
+                mesh.render(render_pass);
             }
         }
     }
 
     fn render_instances<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instances: &Buffer, range: Range<u32>) {
-        // for (i, material) in self.materials.iter().enumerate() {
-        //     render_pass.set_bind_group(i as u32, &material.bind_group, &[]);
-        // }
-        // render_pass.set_vertex_buffer(1, instances.slice(..));
-        for (i, mesh) in self.models.iter().enumerate() {
-            if self.enable_material_binding {
-                if let Some(material) = self.materials.get(i) {
-                    render_pass.set_bind_group(0, &material.bind_group, &[]);
-                }
-            }
-            mesh.render_instances(render_pass, instances, range.clone());
-            // render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            // render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            // render_pass.draw_indexed(0..mesh.num_indices, 0, range.clone());
+        let mut bound = None;
+        for i in self.draw_order() {
+            self.bind_material(render_pass, i, &mut bound);
+            self.models[i].render_instances(render_pass, instances, range.clone());
         }
     }
 }
 
+// Model indices sorted by material so every material only has to be bound once, models keep their order within a material
+pub fn material_draw_order(model_materials: &[usize], num_models: usize) -> Vec<usize> {
+    let mut order = (0..num_models).collect::<Vec<_>>();
+    order.sort_by_key(|i| model_materials.get(*i).copied().unwrap_or(usize::MAX));
+    order
+}
+
 impl MeshModel {
+    pub fn draw_order(&self) -> Vec<usize> {
+        material_draw_order(&self.model_materials, self.models.len())
+    }
+
+    pub fn with_material_group(mut self, group: u32) -> Self {
+        self.material_group = group;
+        self
+    }
+
+    // Only calls set_bind_group when the material differs from the one bound last
+    fn bind_material<'a: 'b, 'b>(&'a self, render_pass: &mut RenderPass<'b>, model: usize, bound: &mut Option<usize>) {
+        if let Some(material_index) = self.material_to_bind(model, bound) {
+            render_pass.set_bind_group(self.material_group, &self.materials[material_index].bind_group, &[]);
+        }
+    }
+
+    // The material `model` needs, None if material binding is off or it is already bound
+    fn material_to_bind(&self, model: usize, bound: &mut Option<usize>) -> Option<usize> {
+        if !self.enable_material_binding {
+            return None;
+        }
+        let material_index = self.model_materials.get(model).copied()?;
+        if *bound == Some(material_index) || material_index >= self.materials.len() {
+            return None;
+        }
+        *bound = Some(material_index);
+        Some(material_index)
+    }
+
     pub fn load_model(
         name: Option<String>,
         source_path: &Path,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        layout: &wgpu::BindGroupLayout,
+        layout: &MaterialLayout,
+    ) -> anyhow::Result<MeshModel> {
+        Self::load_model_with(name, source_path, device, queue, layout, &MeshProcessingOptions::default())
+    }
+
+    // With `options.tangents` the models use `TangentModelVertex` instead of `ModelVertex`
+    pub fn load_model_with(
+        name: Option<String>,
+        source_path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+        options: &MeshProcessingOptions,
+    ) -> anyhow::Result<MeshModel> {
+        let (baked, optimization) = BakedMesh::from_obj(source_path, options, |path| load_resource(path.as_os_str().to_str().unwrap()))?;
+        let mut model = if options.tangents {
+            Self::from_baked::<TangentModelVertex>(name, &baked, device, queue, layout)?
+        } else {
+            Self::from_baked::<ModelVertex>(name, &baked, device, queue, layout)?
+        };
+        model.optimization = optimization;
+        Ok(model)
+    }
+
+    // Loads a mesh baked with `BakedMesh::from_obj`, e.g. the .bmesh files `generate_resources_with` creates. `V` is the
+    // vertex type the pipeline is built for, the file has to have been baked with the same layout
+    pub fn load_baked<V: Descriptor>(
+        name: Option<String>,
+        source_path: &Path,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
     ) -> anyhow::Result<MeshModel> {
         let path_string = source_path.as_os_str().to_str().unwrap();
-        let obj_cursor = Cursor::new(load_resource(path_string).expect(&format!("tried to load resource at {path_string}")));
-        let mut obj_reader = BufReader::new(obj_cursor);
-        
-        let (models, obj_materials) = tobj::load_obj_buf(
-            &mut obj_reader,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-            |p| {
-                tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(load_resource(source_path.parent().unwrap().join(p).as_os_str().to_str().unwrap()).unwrap())))
-            },
-        )?;
-    
-    let mut materials = Vec::new();
-    for m in obj_materials? {
-        if let Some(diffuse_texture) = &m.diffuse_texture {
-            let diffuse_texture = load_texture(source_path.parent().unwrap().join(diffuse_texture).as_os_str().to_str().unwrap(), device, queue)?;
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    },
-                    ],
-                    label: None,
-                });
-                
-                materials.push(Material {
-                    name: m.name,
-                    diffuse_texture,
-                    bind_group,
-                })
+        let bytes = load_resource(path_string).ok_or(anyhow::anyhow!("tried to load resource at {path_string}"))?;
+        Self::from_baked::<V>(name, &BakedMesh::from_bytes(&bytes)?, device, queue, layout)
+    }
+
+    pub fn from_baked<V: Descriptor>(
+        name: Option<String>,
+        baked: &BakedMesh,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+    ) -> anyhow::Result<MeshModel> {
+        if !baked.matches_vertex::<V>() {
+            anyhow::bail!("the baked vertex layout doesn't match {}", std::any::type_name::<V>());
+        }
+        let defaults = DefaultTextures::new(device, queue)?;
+        let mut materials = Vec::new();
+        for m in baked.materials.iter() {
+            let mut textures = HashMap::new();
+            for map in MaterialMap::ALL {
+                let Some(path) = baked.texture_path(m, map) else { continue; };
+                let format = if map.is_color() { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm };
+                textures.insert(map, load_texture_format(path, format, device, queue)?);
             }
+            materials.push(Material::new(device, baked.material_name(m).to_string(), m.values, textures, layout, &defaults));
         }
+        let label = name.unwrap_or("".to_string());
+        let mut models = vec![];
         let mut model_materials = vec![];
-        let models = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-            .map(|i| ModelVertex {
-                position: [
-                    m.mesh.positions[i * 3],
-                    m.mesh.positions[i * 3 + 1],
-                    m.mesh.positions[i * 3 + 2],
-                ],
-                tex_coords: [*m.mesh.texcoords.get(i * 2).unwrap_or(&0.0), 1.0 - *m.mesh.texcoords.get(i * 2 + 1).unwrap_or(&0.0)],
-                normal: [
-                    m.mesh.normals[i * 3],
-                    m.mesh.normals[i * 3 + 1],
-                    m.mesh.normals[i * 3 + 2],
-                    ],
-                })
-                    .collect::<Vec<_>>();
-                
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{} Vertex Buffer", name.clone().unwrap_or("".to_string()))),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{} Index Buffer", name.clone().unwrap_or("".to_string()))),
-                    contents: bytemuck::cast_slice(&m.mesh.indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-                model_materials.push(m.mesh.material_id.unwrap_or(0));
-                Model {
-                    // name: name.clone(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_indices: m.mesh.indices.len() as u32,
-                    index_format: wgpu::IndexFormat::Uint32,
-                    num_vertices: vertices.len() as u32,
-                    num_instances: 0,
-                    instance_buffer: None,
-                    bounding_box: calculate_bounding_box(&vertices),
-                }
-            })
-            .collect::<Vec<_>>();
-        
-        Ok(MeshModel { models, model_materials, materials, enable_material_binding: true })
-    }
-}
-    
+        for submesh in baked.submeshes.iter() {
+            let indices = baked.submesh_indices(submesh)?;
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{label} Vertex Buffer")),
+                contents: baked.submesh_vertices(submesh)?,
+                usage: VERTEX_USAGE,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{label} Index Buffer")),
+                contents: indices,
+                usage: INDEX_USAGE,
+            });
+            models.push(Model {
+                vertex_buffer,
+                index_buffer,
+                num_indices: submesh.index_count,
+                index_format: baked.index_format(),
+                num_vertices: submesh.vertex_count,
+                num_instances: 0,
+                instance_buffer: None,
+                bounding_box: AABB { dimensions: submesh.bounding_box },
+            });
+            if submesh.material as usize >= materials.len() {
+                anyhow::bail!("baked submesh uses material {} but there are only {}", submesh.material, materials.len());
+            }
+            model_materials.push(submesh.material as usize);
+        }
+
+        Ok(MeshModel { models, model_materials, materials, enable_material_binding: true, material_group: 0, optimization: vec![] })
+    }
+}
+
 pub fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
@@ -200,6 +509,17 @@ pub fn load_texture(
     Texture::from_bytes(device, queue, &data, file_name, None, None)
 }
 
+pub fn load_texture_format(
+    file_name: &str,
+    format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let data = load_resource(file_name).ok_or(anyhow::anyhow!("tried to load resource at {file_name}"))?;
+    let img = image::load_from_memory(&data)?;
+    Texture::from_image(device, queue, &img, Some(file_name), Some(format), None, None, None)
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
 pub struct ModelVertex {
@@ -244,4 +564,116 @@ impl ToRaw for ModelVertex {
     fn to_raw(&self) -> Vec<u8> {
         bytes_of(self).to_vec()
     }
-}
\ No newline at end of file
+}
+
+// `ModelVertex` with a tangent for normal mapping, the bitangent handedness is in w
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct TangentModelVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+}
+
+impl VertexTrait for TangentModelVertex {
+    fn pos(&self) -> cgmath::Vector3<f32> {
+        self.position.into()
+    }
+}
+
+impl Descriptor for TangentModelVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+impl ToRaw for TangentModelVertex {
+    fn to_raw(&self) -> Vec<u8> {
+        bytes_of(self).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{baked_mesh::BakedMesh, mesh_processing::MeshProcessingOptions, test_support};
+
+    use super::{material_draw_order, MaterialLayout, MeshModel, ModelVertex, TangentModelVertex};
+
+    const OBJ: &str = "mtllib materials.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+o first
+usemtl red
+f 1/1/1 2/1/1 3/1/1
+o second
+usemtl blue
+f 1/1/1 3/1/1 2/1/1
+o third
+usemtl red
+f 2/1/1 3/1/1 1/1/1
+";
+
+    const MTL: &str = "newmtl red
+Kd 1 0 0
+newmtl blue
+Kd 0 0 1
+";
+
+    #[test]
+    fn multi_material_obj_binds_each_material_once() {
+        let Some((device, queue)) = test_support::device() else { return; };
+        let _lock = test_support::GPU_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let (baked, _) = BakedMesh::from_obj(Path::new("meshes/mesh.obj"), &MeshProcessingOptions::default(), |path| match path.to_str().unwrap().replace("\\", "/").as_str() {
+            "meshes/mesh.obj" => Some(OBJ.as_bytes().to_vec()),
+            "meshes/materials.mtl" => Some(MTL.as_bytes().to_vec()),
+            _ => None,
+        }).unwrap();
+        let layout = MaterialLayout::diffuse(&device);
+        let model = MeshModel::from_baked::<ModelVertex>(None, &baked, &device, &queue, &layout).unwrap();
+        let material_names = model.model_materials.iter().map(|i| model.materials[*i].name.as_str()).collect::<Vec<_>>();
+        assert_eq!(material_names, ["red", "blue", "red"]);
+        assert_eq!(model.model_materials, [0, 1, 0]);
+        assert!(MeshModel::from_baked::<TangentModelVertex>(None, &baked, &device, &queue, &layout).is_err());
+
+        let order = model.draw_order();
+        assert_eq!(order, [0, 2, 1]);
+        assert_eq!(order.iter().map(|i| model.model_materials[*i]).collect::<Vec<_>>(), [0, 0, 1]);
+        // models without a material go last, the rest keep their order within a material
+        assert_eq!(material_draw_order(&[2, 0, 1, 0], 5), [1, 3, 2, 0, 4]);
+
+        let mut bound = None;
+        let binds = order.iter().filter_map(|i| model.material_to_bind(*i, &mut bound)).map(|i| model.materials[i].name.as_str()).collect::<Vec<_>>();
+        assert_eq!(binds, ["red", "blue"]);
+    }
+}
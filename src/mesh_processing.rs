@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+
+use bytemuck::Pod;
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::VertexTrait;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalMode {
+    // every triangle gets its own normal
+    Flat,
+    // averaged over every triangle sharing a position, even across uv seams
+    Smooth,
+    // smooth, but triangles meeting at a sharper angle than this (in degrees) keep a hard edge
+    AngleThreshold(f32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshProcessingOptions {
+    // used when the file has no normals or `recompute_normals` is set
+    pub normals: NormalMode,
+    pub recompute_normals: bool,
+    // loaders switch to a vertex type with a tangent attribute, e.g. `TangentModelVertex` for OBJ
+    pub tangents: bool,
+    // weld, reorder for the vertex cache, overdraw and fetch locality and use u16 indices when possible, see `optimize_mesh`
+    pub optimize: bool,
+}
+
+impl Default for MeshProcessingOptions {
+    fn default() -> Self {
+        Self { normals: NormalMode::Smooth, recompute_normals: false, tangents: false, optimize: false }
+    }
+}
+
+pub struct GeneratedNormals {
+    // the original vertex every new vertex was copied from, vertices get split where a hard edge needs two normals
+    pub remap: Vec<u32>,
+    pub indices: Vec<u32>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+fn position_key(position: [f32; 3]) -> [u32; 3] {
+    // -0.0 and 0.0 should weld
+    position.map(|it| (it + 0.0).to_bits())
+}
+
+pub fn generate_normals(positions: &[[f32; 3]], indices: &[u32], mode: NormalMode) -> GeneratedNormals {
+    let triangles = indices.len() / 3;
+    let face_normals = (0..triangles).map(|face| {
+        let [a, b, c] = [0, 1, 2].map(|corner| Vector3::from(positions[indices[face * 3 + corner] as usize]));
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() > 0.0 { normal.normalize() } else { normal }
+    }).collect::<Vec<_>>();
+    // every corner is weighted by its angle so the result doesn't depend on how the surface was triangulated
+    let corner_angles = (0..triangles * 3).map(|i| {
+        let face = i / 3;
+        let [a, b, c] = [0, 1, 2].map(|corner| Vector3::from(positions[indices[face * 3 + (i + corner) % 3] as usize]));
+        let (ab, ac) = (b - a, c - a);
+        if ab.magnitude2() > 0.0 && ac.magnitude2() > 0.0 { ab.angle(ac).0 } else { 0.0 }
+    }).collect::<Vec<_>>();
+    let mut corners_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (i, index) in indices.iter().enumerate().take(triangles * 3) {
+        corners_at_position.entry(position_key(positions[*index as usize])).or_default().push(i);
+    }
+    let min_cos = match mode {
+        NormalMode::AngleThreshold(degrees) => degrees.to_radians().cos(),
+        _ => -1.0,
+    };
+
+    let mut remap = vec![];
+    let mut new_indices = vec![];
+    let mut normals = vec![];
+    let mut vertices: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    for (i, index) in indices.iter().enumerate().take(triangles * 3) {
+        let face = i / 3;
+        let normal = match mode {
+            NormalMode::Flat => face_normals[face],
+            _ => {
+                corners_at_position[&position_key(positions[*index as usize])].iter()
+                    .filter(|corner| mode == NormalMode::Smooth || face_normals[*corner / 3].dot(face_normals[face]) >= min_cos)
+                    .fold(Vector3::new(0.0, 0.0, 0.0), |sum, corner| sum + face_normals[*corner / 3] * corner_angles[*corner])
+            }
+        };
+        let normal: [f32; 3] = if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0, 1.0, 0.0] };
+        let vertex = *vertices.entry((*index, position_key(normal))).or_insert_with(|| {
+            remap.push(*index);
+            normals.push(normal);
+            remap.len() as u32 - 1
+        });
+        new_indices.push(vertex);
+    }
+    GeneratedNormals { remap, indices: new_indices, normals }
+}
+
+// Per vertex tangents with the bitangent handedness in w, accumulated from the uv gradients of every triangle and
+// orthogonalized against the normal like MikkTSpace. Vertices aren't split, so mirrored uv seams need split vertices already
+pub fn generate_tangents(positions: &[[f32; 3]], normals: &[[f32; 3]], tex_coords: &[[f32; 2]], indices: &[u32]) -> Vec<[f32; 4]> {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    let mut bitangents = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] as usize);
+        let edge1 = Vector3::from(positions[b]) - Vector3::from(positions[a]);
+        let edge2 = Vector3::from(positions[c]) - Vector3::from(positions[a]);
+        let uv_a = Vector2::from(tex_coords.get(a).copied().unwrap_or_default());
+        let delta1 = Vector2::from(tex_coords.get(b).copied().unwrap_or_default()) - uv_a;
+        let delta2 = Vector2::from(tex_coords.get(c).copied().unwrap_or_default()) - uv_a;
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) * r;
+        let bitangent = (edge2 * delta1.x - edge1 * delta2.x) * r;
+        for vertex in [a, b, c] {
+            tangents[vertex] += tangent;
+            bitangents[vertex] += bitangent;
+        }
+    }
+    (0..positions.len()).map(|i| {
+        let normal = Vector3::from(normals.get(i).copied().unwrap_or([0.0, 1.0, 0.0]));
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            // no usable uvs, any direction perpendicular to the normal
+            let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        [tangent.x, tangent.y, tangent.z, handedness]
+    }).collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub triangles: usize,
+    // average cache misses per triangle with a 16 entry FIFO cache, between 0.5 and 3, lower is better
+    pub acmr: f32,
+    // cache misses per vertex, 1 is optimal
+    pub atvr: f32,
+    pub index_format: wgpu::IndexFormat,
+}
+
+impl MeshStats {
+    pub fn measure(num_vertices: usize, indices: &[u32], index_format: wgpu::IndexFormat) -> Self {
+        let misses = cache_misses(indices, 16);
+        let triangles = indices.len() / 3;
+        Self {
+            vertices: num_vertices,
+            triangles,
+            acmr: if triangles > 0 { misses as f32 / triangles as f32 } else { 0.0 },
+            atvr: if num_vertices > 0 { misses as f32 / num_vertices as f32 } else { 0.0 },
+            index_format,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizationReport {
+    pub before: MeshStats,
+    pub after: MeshStats,
+}
+
+fn cache_misses(indices: &[u32], cache_size: usize) -> usize {
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for index in indices {
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*index);
+        }
+    }
+    misses
+}
+
+pub fn smallest_index_format(num_vertices: usize) -> wgpu::IndexFormat {
+    if num_vertices <= u16::MAX as usize + 1 { wgpu::IndexFormat::Uint16 } else { wgpu::IndexFormat::Uint32 }
+}
+
+// Merges vertices with identical bytes
+pub fn weld_vertices<V: Pod>(vertices: &[V], indices: &[u32]) -> (Vec<V>, Vec<u32>) {
+    let mut unique: HashMap<&[u8], u32> = HashMap::new();
+    let mut welded = vec![];
+    let remap = vertices.iter().map(|vertex| *unique.entry(bytemuck::bytes_of(vertex)).or_insert_with(|| {
+        welded.push(*vertex);
+        welded.len() as u32 - 1
+    })).collect::<Vec<_>>();
+    (welded, indices.iter().map(|index| remap[*index as usize]).collect())
+}
+
+const FORSYTH_CACHE_SIZE: usize = 32;
+
+fn forsyth_score(cache_position: Option<usize>, live_triangles: usize) -> f32 {
+    if live_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // the triangle that was just drawn, fixed so new triangles don't only reuse its last edge
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (FORSYTH_CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    // prefer vertices with few triangles left so they leave the cache for good
+    cache_score + 2.0 * (live_triangles as f32).powf(-0.5)
+}
+
+// Tom Forsyth's linear speed vertex cache optimisation, reorders triangles so their vertices are still in the post transform cache
+pub fn optimize_vertex_cache(indices: &[u32], num_vertices: usize) -> Vec<u32> {
+    let triangles = indices.len() / 3;
+    let mut vertex_triangles = vec![vec![]; num_vertices];
+    for (i, index) in indices.iter().enumerate().take(triangles * 3) {
+        vertex_triangles[*index as usize].push(i / 3);
+    }
+    let mut live = vertex_triangles.iter().map(|it| it.len()).collect::<Vec<_>>();
+    let mut cache_positions = vec![None; num_vertices];
+    let mut vertex_scores = (0..num_vertices).map(|vertex| forsyth_score(None, live[vertex])).collect::<Vec<_>>();
+    let triangle_score = |triangle: usize, vertex_scores: &[f32]| (0..3).map(|corner| vertex_scores[indices[triangle * 3 + corner] as usize]).sum::<f32>();
+    let mut triangle_scores = (0..triangles).map(|triangle| triangle_score(triangle, &vertex_scores)).collect::<Vec<_>>();
+    let mut emitted = vec![false; triangles];
+    let mut cache: Vec<u32> = vec![];
+    let mut output = Vec::with_capacity(triangles * 3);
+    let mut best = (0..triangles).max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+    // where to continue looking for triangles once nothing in the cache has any left
+    let mut scan = 0;
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        let corners = [0, 1, 2].map(|corner| indices[triangle * 3 + corner]);
+        output.extend(corners);
+        for vertex in corners {
+            live[vertex as usize] -= 1;
+            cache.retain(|cached| *cached != vertex);
+        }
+        let mut touched = corners.to_vec();
+        touched.extend(cache.iter());
+        cache = corners.iter().chain(cache.iter()).copied().collect();
+        let evicted = cache.split_off(cache.len().min(FORSYTH_CACHE_SIZE));
+        for vertex in &evicted {
+            cache_positions[*vertex as usize] = None;
+        }
+        for (position, vertex) in cache.iter().enumerate() {
+            cache_positions[*vertex as usize] = Some(position);
+        }
+        touched.extend(evicted);
+        best = None;
+        let mut best_score = f32::MIN;
+        for vertex in touched {
+            vertex_scores[vertex as usize] = forsyth_score(cache_positions[vertex as usize], live[vertex as usize]);
+        }
+        for vertex in &cache {
+            for other in &vertex_triangles[*vertex as usize] {
+                if emitted[*other] {
+                    continue;
+                }
+                triangle_scores[*other] = triangle_score(*other, &vertex_scores);
+                if triangle_scores[*other] > best_score {
+                    best_score = triangle_scores[*other];
+                    best = Some(*other);
+                }
+            }
+        }
+        if best.is_none() {
+            while scan < triangles && emitted[scan] {
+                scan += 1;
+            }
+            best = (scan < triangles).then_some(scan);
+        }
+    }
+    output
+}
+
+// Splits the cache optimized triangles into clusters wherever the cache has to restart and draws clusters facing away from
+// the mesh center first, so the outside of a convex-ish mesh tends to be drawn before what it covers (Tipsify's overdraw sort)
+pub fn optimize_overdraw(indices: &[u32], positions: &[[f32; 3]]) -> Vec<u32> {
+    let triangles = indices.len() / 3;
+    if triangles == 0 {
+        return indices.to_vec();
+    }
+    let mut clusters = vec![0];
+    let mut cache = std::collections::VecDeque::with_capacity(16);
+    for triangle in 0..triangles {
+        let mut misses = 0;
+        for index in &indices[triangle * 3..triangle * 3 + 3] {
+            if !cache.contains(index) {
+                misses += 1;
+                if cache.len() == 16 {
+                    cache.pop_front();
+                }
+                cache.push_back(*index);
+            }
+        }
+        if misses == 3 && triangle > *clusters.last().unwrap() {
+            clusters.push(triangle);
+        }
+    }
+    clusters.push(triangles);
+    let corner = |i: usize| Vector3::from(positions[indices[i] as usize]);
+    let mesh_center = (0..triangles * 3).map(corner).fold(Vector3::new(0.0, 0.0, 0.0), |sum, it| sum + it) / (triangles * 3) as f32;
+    let mut sorted = clusters.windows(2).map(|range| {
+        let (mut center, mut normal) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        for triangle in range[0]..range[1] {
+            let [a, b, c] = [0, 1, 2].map(|i| corner(triangle * 3 + i));
+            center += (a + b + c) / 3.0;
+            normal += (b - a).cross(c - a);
+        }
+        center /= (range[1] - range[0]) as f32;
+        let facing = if normal.magnitude2() > 0.0 { (center - mesh_center).dot(normal.normalize()) } else { 0.0 };
+        (facing, range[0], range[1])
+    }).collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    sorted.iter().flat_map(|(_, start, end)| indices[start * 3..end * 3].iter().copied()).collect()
+}
+
+// Renumbers vertices in the order the index buffer first uses them, unused vertices are dropped
+pub fn optimize_vertex_fetch<V: Copy>(vertices: &[V], indices: &[u32]) -> (Vec<V>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut reordered = vec![];
+    let indices = indices.iter().map(|index| {
+        if remap[*index as usize] == u32::MAX {
+            remap[*index as usize] = reordered.len() as u32;
+            reordered.push(vertices[*index as usize]);
+        }
+        remap[*index as usize]
+    }).collect();
+    (reordered, indices)
+}
+
+// Runs every step above, the report's `after.index_format` is the one the mesh should be uploaded with
+pub fn optimize_mesh<V: Pod + VertexTrait>(vertices: Vec<V>, indices: Vec<u32>, index_format: wgpu::IndexFormat) -> (Vec<V>, Vec<u32>, OptimizationReport) {
+    let before = MeshStats::measure(vertices.len(), &indices, index_format);
+    let (vertices, indices) = weld_vertices(&vertices, &indices);
+    let indices = optimize_vertex_cache(&indices, vertices.len());
+    let positions = vertices.iter().map(|vertex| vertex.pos().into()).collect::<Vec<[f32; 3]>>();
+    let indices = optimize_overdraw(&indices, &positions);
+    let (vertices, indices) = optimize_vertex_fetch(&vertices, &indices);
+    let after = MeshStats::measure(vertices.len(), &indices, smallest_index_format(vertices.len()));
+    (vertices, indices, OptimizationReport { before, after })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{baked_mesh::BakedMesh, mesh::ModelVertex};
+
+    use super::{generate_normals, generate_tangents, optimize_mesh, MeshProcessingOptions, NormalMode};
+
+    fn assert_near<const N: usize>(actual: [f32; N], expected: [f32; N]) {
+        assert!(actual.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-5), "{actual:?} != {expected:?}");
+    }
+
+    #[test]
+    fn flat_quad_gets_up_normals_and_x_tangents() {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+        let indices = [0, 1, 2, 0, 2, 3];
+        let generated = generate_normals(&positions, &indices, NormalMode::Smooth);
+        assert_eq!(generated.remap, [0, 1, 2, 3]);
+        assert_eq!(generated.indices, indices);
+        for normal in &generated.normals {
+            assert_near(*normal, [0.0, 0.0, 1.0]);
+        }
+        let tex_coords = positions.map(|[x, y, _]| [x, y]);
+        for tangent in generate_tangents(&positions, &generated.normals, &tex_coords, &indices) {
+            assert_near(tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+        // v pointing down the quad mirrors the bitangent
+        let tex_coords = positions.map(|[x, y, _]| [x, 1.0 - y]);
+        for tangent in generate_tangents(&positions, &generated.normals, &tex_coords, &indices) {
+            assert_near(tangent, [1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn sharp_edges_split_vertices() {
+        // two triangles folded 90 degrees along the edge from 0 to 2, facing +Z and +X
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let indices = [0, 1, 2, 0, 2, 3];
+        let smooth = generate_normals(&positions, &indices, NormalMode::Smooth);
+        assert_eq!(smooth.normals.len(), 4);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(smooth.normals[0], [half, 0.0, half]);
+        assert_near(smooth.normals[1], [0.0, 0.0, 1.0]);
+        for mode in [NormalMode::Flat, NormalMode::AngleThreshold(30.0)] {
+            let split = generate_normals(&positions, &indices, mode);
+            assert_eq!(split.remap, [0, 1, 2, 0, 2, 3]);
+            for (corner, vertex) in split.indices.iter().enumerate() {
+                assert_near(split.normals[*vertex as usize], if corner < 3 { [0.0, 0.0, 1.0] } else { [1.0, 0.0, 0.0] });
+            }
+        }
+        // below the threshold the edge stays smooth
+        assert_eq!(generate_normals(&positions, &indices, NormalMode::AngleThreshold(100.0)).normals.len(), 4);
+    }
+
+    #[test]
+    fn normals_from_the_file_are_kept() {
+        // the file's normal points along +Y although the triangle faces +Z
+        const OBJ: &str = "v 0 0 0
+v 1 0 0
+v 0 1 0
+vn 0 1 0
+f 1//1 2//1 3//1
+";
+        let normals = |recompute_normals| {
+            let options = MeshProcessingOptions { recompute_normals, ..Default::default() };
+            let (baked, _) = BakedMesh::from_obj(Path::new("mesh.obj"), &options, |_| Some(OBJ.as_bytes().to_vec())).unwrap();
+            bytemuck::pod_collect_to_vec::<u8, ModelVertex>(&baked.vertex_data).iter().map(|vertex| vertex.normal).collect::<Vec<_>>()
+        };
+        assert_eq!(normals(false), [[0.0, 1.0, 0.0]; 3]);
+        assert_eq!(normals(true), [[0.0, 0.0, 1.0]; 3]);
+    }
+
+    // every triangle as its corner positions, rotated to start at the smallest so the winding is kept
+    fn triangles(vertices: &[ModelVertex], indices: &[u32]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles = indices.chunks_exact(3).map(|triangle| {
+            let corners = [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].position.map(f32::to_bits));
+            let first = (0..3).min_by_key(|i| corners[*i]).unwrap();
+            [0, 1, 2].map(|corner| corners[(first + corner) % 3])
+        }).collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn optimize_mesh_welds_and_keeps_the_triangles() {
+        // a 2x2 grid of quads with its own 3 vertices for every triangle, 9 of the 24 are unique
+        let vertex = |x: f32, y: f32| ModelVertex { position: [x, y, 0.0], tex_coords: [x, y], normal: [0.0, 0.0, 1.0] };
+        let vertices = (0..4).flat_map(|quad| {
+            let (x, y) = ((quad % 2) as f32, (quad / 2) as f32);
+            [vertex(x, y), vertex(x + 1.0, y), vertex(x + 1.0, y + 1.0), vertex(x, y), vertex(x + 1.0, y + 1.0), vertex(x, y + 1.0)]
+        }).collect::<Vec<_>>();
+        let indices = (0..vertices.len() as u32).collect::<Vec<_>>();
+        let (optimized, optimized_indices, report) = optimize_mesh(vertices.clone(), indices.clone(), wgpu::IndexFormat::Uint32);
+        assert_eq!(triangles(&optimized, &optimized_indices), triangles(&vertices, &indices));
+        assert_eq!(optimized.len(), 9);
+        assert!(optimized_indices.iter().all(|index| (*index as usize) < optimized.len()));
+
+        assert_eq!((report.before.vertices, report.before.triangles, report.before.index_format), (24, 8, wgpu::IndexFormat::Uint32));
+        // every index misses the cache before welding
+        assert_eq!((report.before.acmr, report.before.atvr), (3.0, 1.0));
+        assert_eq!((report.after.vertices, report.after.triangles, report.after.index_format), (9, 8, wgpu::IndexFormat::Uint16));
+        // all 9 vertices fit in the cache, so each one misses once
+        assert_eq!((report.after.acmr, report.after.atvr), (9.0 / 8.0, 1.0));
+    }
+}
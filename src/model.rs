@@ -2,7 +2,7 @@ use std::ops::Range;
 
 use bytemuck::{cast_slice, Pod};
 use cgmath::{Matrix4, SquareMatrix};
-use wgpu::{util::DeviceExt, Buffer, IndexFormat, RenderPass};
+use wgpu::{util::DeviceExt, Buffer, BufferUsages, Device, IndexFormat, Queue, RenderPass};
 
 use crate::{binding::UniformBinding, camera::Camera, culling::{culled, CullingCompute, AABB}, surface_context::SurfaceCtx, VertexTrait};
 
@@ -28,7 +28,7 @@ impl Model {
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Instance Buffer"),
                 contents: &instances.iter().map(|instance| instance.to_raw()).collect::<Vec<_>>().concat(),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+                usage: INSTANCE_USAGE,
             }
         );
         let num_indices = indices.len() as u32;
@@ -89,7 +89,7 @@ impl Model {
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Instance Buffer"),
                 contents: &instances.iter().map(|instance| instance.to_raw()).collect::<Vec<_>>().concat(),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+                usage: INSTANCE_USAGE,
             }
         );
         let num_instances = instances.len() as u32;
@@ -109,36 +109,129 @@ impl Model {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: vertices,
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: VERTEX_USAGE,
         });
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
             contents: indices,
-            usage: wgpu::BufferUsages::INDEX,
+            usage: INDEX_USAGE,
         });
         [vertex_buffer, index_buffer]
     }
 
-    pub fn update_instances(&mut self, instances: Vec<impl ToRaw>, device: & dyn DeviceExt) {
-        self.instance_buffer = Some(device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: &instances.iter().map(|instance| instance.to_raw()).collect::<Vec<_>>().concat(),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
-            }
-        ));
-        self.num_instances = instances.len() as u32;
+    // Replaces the vertices, reusing the buffer when it's big enough and doubling its size otherwise
+    pub fn update_vertices<T: Pod + VertexTrait>(&mut self, vertices: &[T], device: &Device, queue: &Queue) {
+        write_growing(&mut self.vertex_buffer, "Vertex Buffer", 0, cast_slice(vertices), false, device, queue);
+        self.num_vertices = vertices.len() as u32;
+        self.bounding_box = calculate_bounding_box(&vertices.to_vec());
     }
 
-    pub fn update_instances_raw<T: Pod>(&mut self, instances: &[T], device: & dyn DeviceExt) {
-        self.instance_buffer = Some(device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
+    // Overwrites the vertices starting at `first_vertex`, growing the buffer if they go past its end.
+    // The bounding box only grows, it's never shrunk to fit
+    pub fn write_vertices<T: Pod + VertexTrait>(&mut self, first_vertex: u32, vertices: &[T], device: &Device, queue: &Queue) -> anyhow::Result<()> {
+        let offset = first_vertex as u64 * std::mem::size_of::<T>() as u64;
+        check_alignment(offset, std::mem::size_of_val(vertices))?;
+        write_growing(&mut self.vertex_buffer, "Vertex Buffer", offset, cast_slice(vertices), true, device, queue);
+        self.num_vertices = self.num_vertices.max(first_vertex + vertices.len() as u32);
+        let written = calculate_bounding_box(&vertices.to_vec());
+        self.bounding_box = AABB { dimensions: [0, 1, 2].map(|i| self.bounding_box.dimensions[i].max(written.dimensions[i])) };
+        Ok(())
+    }
+
+    pub fn update_indices<I: IndexFormatType + Pod>(&mut self, indices: &[I], device: &Device, queue: &Queue) {
+        write_growing(&mut self.index_buffer, "Index Buffer", 0, cast_slice(indices), false, device, queue);
+        self.num_indices = indices.len() as u32;
+        self.index_format = I::get_index_format();
+    }
+
+    // u16 indices can only be written in pairs starting at an even index because buffer writes are 4 byte aligned
+    pub fn write_indices<I: IndexFormatType + Pod>(&mut self, first_index: u32, indices: &[I], device: &Device, queue: &Queue) -> anyhow::Result<()> {
+        if I::get_index_format() != self.index_format {
+            anyhow::bail!("tried to write {:?} indices into a {:?} index buffer", I::get_index_format(), self.index_format);
+        }
+        let offset = first_index as u64 * std::mem::size_of::<I>() as u64;
+        check_alignment(offset, std::mem::size_of_val(indices))?;
+        write_growing(&mut self.index_buffer, "Index Buffer", offset, cast_slice(indices), true, device, queue);
+        self.num_indices = self.num_indices.max(first_index + indices.len() as u32);
+        Ok(())
+    }
+
+    pub fn update_instances(&mut self, instances: Vec<impl ToRaw>, device: &Device, queue: &Queue) {
+        self.update_instance_bytes(&instances.iter().map(|instance| instance.to_raw()).collect::<Vec<_>>().concat(), instances.len() as u32, device, queue);
+    }
+
+    pub fn update_instances_raw<T: Pod>(&mut self, instances: &[T], device: &Device, queue: &Queue) {
+        self.update_instance_bytes(cast_slice(instances), instances.len() as u32, device, queue);
+    }
+
+    fn update_instance_bytes(&mut self, bytes: &[u8], num_instances: u32, device: &Device, queue: &Queue) {
+        match &mut self.instance_buffer {
+            Some(instance_buffer) => write_growing(instance_buffer, "Instance Buffer", 0, bytes, false, device, queue),
+            None => self.instance_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Instance Buffer"),
-                contents: cast_slice(instances),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
-            }
-        ));
-        self.num_instances = instances.len() as u32;
+                contents: bytes,
+                usage: INSTANCE_USAGE,
+            })),
+        }
+        self.num_instances = num_instances;
+    }
+
+    pub fn write_instances<T: Pod>(&mut self, first_instance: u32, instances: &[T], device: &Device, queue: &Queue) -> anyhow::Result<()> {
+        let offset = first_instance as u64 * std::mem::size_of::<T>() as u64;
+        check_alignment(offset, std::mem::size_of_val(instances))?;
+        let instance_buffer = self.instance_buffer.get_or_insert_with(|| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: 0,
+            usage: INSTANCE_USAGE,
+            mapped_at_creation: false,
+        }));
+        write_growing(instance_buffer, "Instance Buffer", offset, cast_slice(instances), true, device, queue);
+        self.num_instances = self.num_instances.max(first_instance + instances.len() as u32);
+        Ok(())
+    }
+}
+
+// COPY_DST for in place updates and COPY_SRC to keep the old contents when a buffer has to grow
+pub const VERTEX_USAGE: BufferUsages = BufferUsages::VERTEX.union(BufferUsages::COPY_DST).union(BufferUsages::COPY_SRC);
+pub const INDEX_USAGE: BufferUsages = BufferUsages::INDEX.union(BufferUsages::COPY_DST).union(BufferUsages::COPY_SRC);
+pub const INSTANCE_USAGE: BufferUsages = BufferUsages::VERTEX.union(BufferUsages::STORAGE).union(BufferUsages::COPY_DST).union(BufferUsages::COPY_SRC);
+
+fn check_alignment(offset: u64, size: usize) -> anyhow::Result<()> {
+    if !offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) || !(size as u64).is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+        anyhow::bail!("buffer writes have to start and end on a multiple of {} bytes, got offset {offset} and size {size}", wgpu::COPY_BUFFER_ALIGNMENT);
+    }
+    Ok(())
+}
+
+// Writes `data` at `offset`. When it doesn't fit the buffer is replaced by one at least twice as big,
+// copying the old contents over when `keep` is set
+pub fn write_growing(buffer: &mut Buffer, label: &str, offset: u64, data: &[u8], keep: bool, device: &Device, queue: &Queue) {
+    // whole buffer updates may end on an odd u16 index, the padding past the end is never read
+    let padded;
+    let data = if !(data.len() as u64).is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+        padded = [data, &[0; 4][..(wgpu::COPY_BUFFER_ALIGNMENT - data.len() as u64 % wgpu::COPY_BUFFER_ALIGNMENT) as usize]].concat();
+        &padded[..]
+    } else {
+        data
+    };
+    let end = offset + data.len() as u64;
+    // buffers from `new_buffers` might not allow writes, they get replaced as well
+    if end > buffer.size() || !buffer.usage().contains(BufferUsages::COPY_DST) {
+        let grown = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: if end > buffer.size() { end.max(buffer.size() * 2) } else { buffer.size() },
+            usage: buffer.usage() | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        if keep && buffer.size() > 0 && buffer.usage().contains(BufferUsages::COPY_SRC) {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Buffer Growth Encoder") });
+            encoder.copy_buffer_to_buffer(buffer, 0, &grown, 0, buffer.size());
+            queue.submit([encoder.finish()]);
+        }
+        *buffer = grown;
+    }
+    if !data.is_empty() {
+        queue.write_buffer(buffer, offset, data);
     }
 }
 
@@ -0,0 +1,136 @@
+use wgpu::{BindGroupLayout, CommandEncoder, Device, RenderPass, TextureFormat, TextureView};
+
+use crate::{binding::{Descriptor, Uniform}, model::Render, render_target::{RenderTarget, RenderTargetConfig}, shader::{ScenePipeline, Shader, ShaderConfig, ShaderType}, surface_context::SurfaceCtx, window::BasicVertex};
+
+// the weighted sum of the premultiplied colors and the revealage, see oit.wgsl
+pub const OIT_FORMATS: [TextureFormat; 2] = [TextureFormat::Rgba16Float, TextureFormat::R16Float];
+
+pub const OIT_ACCUM_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::One, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+    alpha: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::One, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+};
+
+// every layer multiplies what's left visible behind it by 1 - alpha
+pub const OIT_REVEALAGE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::Zero, dst_factor: wgpu::BlendFactor::OneMinusSrc, operation: wgpu::BlendOperation::Add },
+    alpha: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::Zero, dst_factor: wgpu::BlendFactor::OneMinusSrc, operation: wgpu::BlendOperation::Add },
+};
+
+// Owned handles to the OIT attachments and the scene depth, so the pass doesn't borrow the renderer while the handler draws into it
+pub struct OitPass {
+    // [attachment, resolve target]
+    colors: Vec<(TextureView, Option<TextureView>)>,
+    clear_colors: Vec<wgpu::Color>,
+    depth: TextureView,
+}
+
+impl OitPass {
+    pub fn begin<'e>(&self, encoder: &'e mut CommandEncoder) -> RenderPass<'e> {
+        let color_attachments = self.colors.iter().zip(&self.clear_colors).map(|((view, resolve_target), clear_color)| Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: resolve_target.as_ref(),
+            depth_slice: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(*clear_color),
+                store: wgpu::StoreOp::Store,
+            },
+        })).collect::<Vec<_>>();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Pass"),
+            color_attachments: &color_attachments,
+            multiview_mask: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            // transparent surfaces are only hidden behind the opaque ones, they don't write depth
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
+
+// Weighted blended order independent transparency for lots of overlapping surfaces like particles, where sorting
+// doesn't work. Transparent geometry is drawn in any order into the OIT targets against the scene depth by
+// `WindowHandler::render_oit`, then composited onto the scene texture after `WindowHandler::render`
+pub struct OitRenderer {
+    pub target: RenderTarget,
+    composite_shader: ScenePipeline,
+}
+
+impl OitRenderer {
+    pub fn new(surface_ctx: &dyn SurfaceCtx) -> Self {
+        let device = surface_ctx.device();
+        let scene_target = surface_ctx.scene_target();
+        let target = RenderTarget::new(device, "OIT", scene_target.width, scene_target.height, RenderTargetConfig {
+            formats: OIT_FORMATS.to_vec(),
+            depth: false,
+            sample_count: scene_target.sample_count,
+            clear_colors: vec![wgpu::Color::TRANSPARENT, wgpu::Color::WHITE],
+            ..Default::default()
+        });
+        let uniforms: [&dyn Uniform; 2] = [&target.colors[0], &target.colors[1]];
+        let layouts = uniforms.iter().map(|uniform| uniform.layout().clone()).collect::<Vec<_>>();
+        let shader_types = uniforms.iter().map(|uniform| uniform.shader_type().clone()).collect::<Vec<_>>();
+        Self {
+            composite_shader: ScenePipeline::new(surface_ctx, move |device, scene_format, sample_count| Self::composite_shader(device, &layouts, &shader_types, scene_format, sample_count)),
+            target,
+        }
+    }
+
+    // The config for shaders drawing into the OIT pass, their fragment shaders return `OitOutput`
+    pub fn shader_config(&self) -> ShaderConfig {
+        ShaderConfig {
+            background: false,
+            face_cull: None,
+            multisample_count: self.target.sample_count,
+            target_blends: vec![Some(OIT_ACCUM_BLEND), Some(OIT_REVEALAGE_BLEND)],
+            ..Default::default()
+        }
+    }
+
+    fn composite_shader(device: &Device, layouts: &[BindGroupLayout], shader_types: &[ShaderType], scene_format: TextureFormat, sample_count: u32) -> Shader<'static> {
+        Shader::new(
+            "buildins/oit_composite.wgsl",
+            device,
+            vec![scene_format],
+            layouts.iter().collect(),
+            shader_types.iter().collect(),
+            vec![BasicVertex::desc()],
+            ShaderConfig { background: false, face_cull: None, depth_compare: wgpu::CompareFunction::Always, multisample_count: sample_count, ..Default::default() },
+        )
+    }
+
+    // Follows the size and multisample count of the scene texture, called by the window before every frame
+    pub fn prepare(&mut self, surface_ctx: &dyn SurfaceCtx) {
+        let device = surface_ctx.device();
+        let scene_target = surface_ctx.scene_target();
+        self.target.resize(device, scene_target.width, scene_target.height);
+        self.target.set_sample_count(device, scene_target.sample_count);
+    }
+
+    pub fn pass(&self, surface_ctx: &dyn SurfaceCtx) -> OitPass {
+        let colors = self.target.colors.iter().enumerate().map(|(i, color)| match self.target.multisample_colors.get(i) {
+            Some(multisample_color) => (multisample_color.view.clone(), Some(color.value.view.clone())),
+            None => (color.value.view.clone(), None),
+        }).collect();
+        OitPass {
+            colors,
+            clear_colors: (0..OIT_FORMATS.len()).map(|i| self.target.clear_color(i)).collect(),
+            depth: surface_ctx.scene_target().depth_texture().unwrap().view.clone(),
+        }
+    }
+
+    // Blends the transparent surfaces onto the scene texture
+    pub fn composite(&self, surface_ctx: &dyn SurfaceCtx, encoder: &mut CommandEncoder) {
+        let mut render_pass = surface_ctx.scene_target().begin_render_pass(encoder, false);
+        self.composite_shader.bind(surface_ctx, &mut render_pass);
+        render_pass.set_bind_group(0, &self.target.colors[0].binding, &[]);
+        render_pass.set_bind_group(1, &self.target.colors[1].binding, &[]);
+        surface_ctx.screen_model().render(&mut render_pass);
+    }
+}
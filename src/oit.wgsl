@@ -0,0 +1,19 @@
+// Weighted blended order independent transparency, fragment shaders drawing into an `OitRenderer` pass
+// return `oit_output(color, in.clip_position.z)` with a straight alpha color
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: vec4<f32>,
+};
+
+// the weight falls off with the depth buffer value so nearer surfaces count for more
+fn oit_weight(alpha: f32, depth: f32) -> f32 {
+    return clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 3e3 * pow(1.0 - depth, 3.0), 1e-2, 3e3);
+}
+
+fn oit_output(color: vec4<f32>, depth: f32) -> OitOutput {
+    let weight = oit_weight(color.a, depth);
+    var out: OitOutput;
+    out.accum = vec4(color.rgb * color.a, color.a) * weight;
+    out.revealage = vec4(color.a);
+    return out;
+}
@@ -0,0 +1,37 @@
+accum_texture: $0,0;
+accum_sampler: $0,1;
+revealage_texture: $1,0;
+revealage_sampler: $1,1;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+// The weighted average color with the coverage left over from the revealage as alpha, alpha blended onto the scene
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let revealage = textureSample(revealage_texture, revealage_sampler, in.tex_coords).r;
+    // nothing transparent was drawn here
+    if revealage >= 0.9999 {
+        discard;
+    }
+    let accum = textureSample(accum_texture, accum_sampler, in.tex_coords);
+    let average = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    return vec4(average, 1.0 - revealage);
+}
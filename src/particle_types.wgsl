@@ -0,0 +1,46 @@
+// Shared by the particle update and render shaders, the including shader declares the `emitter` uniform
+struct EmitterSettings {
+    position: vec3f,
+    spawn_rate: f32,
+    direction: vec3f,
+    spread: f32,
+    gravity: vec3f,
+    speed: f32,
+    colors: array<vec4f, 4>,
+    sizes: vec4f,
+    lifetime: f32,
+    lifetime_variance: f32,
+    speed_variance: f32,
+    drag: f32,
+    ground_height: f32,
+    bounce: f32,
+    collide: u32,
+    radius: f32,
+};
+
+// a particle is dead once its age reaches its lifetime, the zeroed buffer starts out all dead
+struct Particle {
+    position: vec3f,
+    age: f32,
+    velocity: vec3f,
+    lifetime: f32,
+};
+
+// the curve keys sit at 0, 1/3, 2/3 and 1 of the lifetime
+fn curve_segment(t: f32) -> vec2f {
+    let x = clamp(t, 0.0, 1.0) * 3.0;
+    let i = min(floor(x), 2.0);
+    return vec2(i, x - i);
+}
+
+fn emitter_color(t: f32) -> vec4f {
+    let segment = curve_segment(t);
+    let i = u32(segment.x);
+    return mix(emitter.colors[i], emitter.colors[i + 1u], segment.y);
+}
+
+fn emitter_size(t: f32) -> f32 {
+    let segment = curve_segment(t);
+    let i = u32(segment.x);
+    return mix(emitter.sizes[i], emitter.sizes[i + 1u], segment.y);
+}
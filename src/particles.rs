@@ -0,0 +1,325 @@
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, Buffer, Device, Queue, RenderPass, TextureFormat};
+
+use crate::{billboard, binding::{Descriptor, Uniform, UniformBinding, WgslType}, compute::ComputeShader, model::{calculate_bounding_box, Model, Render}, oit::OIT_FORMATS, shader::{ScenePipeline, Shader, ShaderConfig, ShaderType}, surface_context::SurfaceCtx, texture::Texture};
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+pub struct EmitterSettings {
+    pub position: [f32; 3],
+    // particles per second, `ParticleSystem::burst` spawns on top of this
+    pub spawn_rate: f32,
+    pub direction: [f32; 3],
+    // half angle in radians of the cone the particles are emitted in around `direction`
+    pub spread: f32,
+    pub gravity: [f32; 3],
+    pub speed: f32,
+    // straight alpha colors and sizes over the lifetime, keyed at 0, 1/3, 2/3 and 1
+    pub colors: [[f32; 4]; 4],
+    pub sizes: [f32; 4],
+    // seconds
+    pub lifetime: f32,
+    // the fraction the lifetime and speed of each particle randomly vary by
+    pub lifetime_variance: f32,
+    pub speed_variance: f32,
+    // how quickly the velocity decays, per second
+    pub drag: f32,
+    pub ground_height: f32,
+    // the fraction of the velocity kept when hitting the ground
+    pub bounce: f32,
+    // 0 lets particles fall through the ground plane
+    pub collide: u32,
+    // particles spawn anywhere within this distance of `position`
+    pub radius: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            spawn_rate: 100.0,
+            direction: [0.0, 1.0, 0.0],
+            spread: 0.4,
+            gravity: [0.0, -9.81, 0.0],
+            speed: 5.0,
+            colors: [[1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 0.5], [1.0, 1.0, 1.0, 0.0]],
+            sizes: [0.1; 4],
+            lifetime: 2.0,
+            lifetime_variance: 0.2,
+            speed_variance: 0.2,
+            drag: 0.1,
+            ground_height: 0.0,
+            bounce: 0.4,
+            collide: 1,
+            radius: 0.0,
+        }
+    }
+}
+
+impl WgslType for EmitterSettings {
+    fn wgsl_name() -> String {
+        "EmitterSettings".into()
+    }
+}
+
+// what the update shader needs to know about the current frame, see particles_update.wgsl
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, Default)]
+struct ParticleFrame {
+    delta_time: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+}
+
+impl WgslType for ParticleFrame {
+    fn wgsl_name() -> String {
+        "ParticleFrame".into()
+    }
+}
+
+// The layout of the particle buffer, which is both the storage buffer of the update shader and the instance buffer
+// of the render shader
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, Default)]
+pub struct Particle {
+    pub position: [f32; 3],
+    pub age: f32,
+    pub velocity: [f32; 3],
+    pub lifetime: f32,
+}
+
+impl Descriptor for Particle {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+pub const ADDITIVE_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::SrcAlpha, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+    alpha: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::Zero, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParticleBlend {
+    // regular alpha blending, the particles aren't sorted so this only looks right for sparse or opaque-ish ones
+    Alpha,
+    Additive,
+    // drawn into an `OitRenderer` pass from `WindowHandler::render_oit`
+    Oit,
+}
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Particles spawned by an emitter and simulated entirely on the GPU. The particles live in a ring buffer that the
+// update shader spawns into and integrates every `update`, and that the render shader reads as its instance buffer,
+// so nothing is ever read back. The render shaders get the camera at group 0, the settings at 1 and the sprite at 2.
+// When more particles are alive than fit in the buffer the oldest ones are replaced, so size it for
+// `spawn_rate * lifetime` plus any bursts
+pub struct ParticleSystem {
+    pub settings: UniformBinding<EmitterSettings>,
+    frame: UniformBinding<ParticleFrame>,
+    particles: Buffer,
+    particles_binding: BindGroup,
+    capacity: u32,
+    quad: Model,
+    sprite: UniformBinding<Texture>,
+    camera: BindGroup,
+    update_shader: ComputeShader,
+    shader: ScenePipeline,
+    // where the next particles are spawned in the ring buffer
+    spawn_start: u32,
+    // particles owed by the spawn rate and bursts that haven't been spawned yet
+    pending: f32,
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(surface_ctx: &dyn SurfaceCtx, capacity: u32, settings: EmitterSettings, sprite: Texture, blend: ParticleBlend, camera: &dyn Uniform) -> Self {
+        let device = surface_ctx.device();
+        let settings = UniformBinding::new(device, "Emitter Settings", settings, None);
+        let frame = UniformBinding::new(device, "Particle Frame", ParticleFrame::default(), None);
+        let sprite = UniformBinding::new(device, "Particle Sprite", sprite, None);
+        // wgpu zero initializes buffers, which makes every particle dead
+        let particles = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (capacity.max(1) as usize * size_of::<Particle>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let particles_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Particle Buffer Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage {
+                        read_only: false,
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }]
+        });
+        let particles_binding = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Particle Buffer Binding"),
+            layout: &particles_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: particles.as_entire_binding(),
+            }]
+        });
+        let update_shader = ComputeShader::new(
+            include_str!("particles_update.wgsl"),
+            vec![&particles_layout, &settings.layout, &frame.layout],
+            vec![&ShaderType::buffer_type(true, "Particle".into()), &settings.shader_type, &frame.shader_type],
+            device,
+        );
+        let uniforms: [&dyn Uniform; 3] = [camera, &settings, &sprite];
+        let layouts = uniforms.iter().map(|uniform| uniform.layout().clone()).collect::<Vec<_>>();
+        let shader_types = uniforms.iter().map(|uniform| uniform.shader_type().clone()).collect::<Vec<_>>();
+        Self {
+            shader: ScenePipeline::new(surface_ctx, move |device, scene_format, sample_count| Self::shader(device, blend, scene_format, sample_count, &layouts, &shader_types)),
+            camera: camera.binding().clone(),
+            quad: Self::quad(device),
+            settings,
+            frame,
+            particles,
+            particles_binding,
+            capacity: capacity.max(1),
+            sprite,
+            update_shader,
+            spawn_start: 0,
+            pending: 0.0,
+            seed: 0,
+        }
+    }
+
+    // A soft round dot to use as the sprite, white so the color curve decides the color
+    pub fn soft_sprite(device: &Device, queue: &Queue) -> anyhow::Result<Texture> {
+        const SIZE: u32 = 32;
+        let img = image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            let center = (SIZE as f32 - 1.0) / 2.0;
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt() / (SIZE as f32 / 2.0);
+            let alpha = (1.0 - distance).clamp(0.0, 1.0).powi(2);
+            image::Rgba([255, 255, 255, (alpha * 255.0) as u8])
+        });
+        Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), Some("Soft Particle Sprite"), None, None, Some(wgpu::FilterMode::Linear), Some(wgpu::AddressMode::ClampToEdge))
+    }
+
+    // the unit billboard quad every particle is an instance of, scaled by the size curve in the shader
+    fn quad(device: &Device) -> Model {
+        let vertices = vec![
+            billboard::Vertex { position: [-0.5, -0.5, 0.0], tex_pos: [0.0, 1.0], normal: [0.0, 0.0, 1.0] },
+            billboard::Vertex { position: [-0.5, 0.5, 0.0], tex_pos: [0.0, 0.0], normal: [0.0, 0.0, 1.0] },
+            billboard::Vertex { position: [0.5, -0.5, 0.0], tex_pos: [1.0, 1.0], normal: [0.0, 0.0, 1.0] },
+            billboard::Vertex { position: [0.5, 0.5, 0.0], tex_pos: [1.0, 0.0], normal: [0.0, 0.0, 1.0] },
+        ];
+        let bounding_box = calculate_bounding_box(&vertices);
+        Model::new(vertices, &[0_u16, 1, 2, 2, 1, 3], bounding_box, device)
+    }
+
+    fn shader(device: &Device, blend: ParticleBlend, scene_format: TextureFormat, sample_count: u32, layouts: &[BindGroupLayout], shader_types: &[ShaderType]) -> Shader<'static> {
+        let (path, formats, config) = match blend {
+            ParticleBlend::Alpha => ("buildins/particles.wgsl", vec![scene_format], ShaderConfig::default()),
+            ParticleBlend::Additive => ("buildins/particles.wgsl", vec![scene_format], ShaderConfig { blend: Some(ADDITIVE_BLENDING), ..Default::default() }),
+            ParticleBlend::Oit => ("buildins/particles_oit.wgsl", OIT_FORMATS.to_vec(), ShaderConfig {
+                target_blends: vec![Some(crate::oit::OIT_ACCUM_BLEND), Some(crate::oit::OIT_REVEALAGE_BLEND)],
+                ..Default::default()
+            }),
+        };
+        Shader::new(
+            path,
+            device,
+            formats,
+            layouts.iter().collect(),
+            shader_types.iter().collect(),
+            vec![billboard::Vertex::desc(), Particle::desc()],
+            ShaderConfig { background: false, face_cull: None, multisample_count: sample_count, ..config },
+        )
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn set_settings(&mut self, queue: &Queue, settings: EmitterSettings) {
+        self.settings.set_data(queue, settings);
+    }
+
+    // Spawns `count` particles on the next `update` on top of the spawn rate
+    pub fn burst(&mut self, count: u32) {
+        self.pending += count as f32;
+    }
+
+    // Spawns the particles due since the last update and moves the rest along, call it once per frame from
+    // `WindowHandler::update`
+    pub fn update(&mut self, surface_ctx: &dyn SurfaceCtx, delta_time: Duration) {
+        let (device, queue) = (surface_ctx.device(), surface_ctx.queue());
+        let delta_time = delta_time.as_secs_f32();
+        self.pending += self.settings.value.spawn_rate.max(0.0) * delta_time;
+        let spawn = self.pending.floor();
+        self.pending -= spawn;
+        let spawn_count = (spawn as u32).min(self.capacity);
+        self.frame.set_data(queue, ParticleFrame {
+            delta_time,
+            spawn_start: self.spawn_start,
+            spawn_count,
+            seed: self.seed,
+        });
+        self.spawn_start = (self.spawn_start + spawn_count) % self.capacity;
+        self.seed = self.seed.wrapping_add(1);
+        let groups = self.capacity.div_ceil(WORKGROUP_SIZE);
+        self.update_shader.run_once(
+            vec![&self.particles_binding, &self.settings.binding, &self.frame.binding],
+            [groups.min(65535), groups.div_ceil(65535), 1],
+            device,
+            queue,
+        );
+    }
+
+    // Kills every particle
+    pub fn clear(&mut self, queue: &Queue) {
+        queue.write_buffer(&self.particles, 0, &vec![0; self.particles.size() as usize]);
+        self.pending = 0.0;
+    }
+
+    // Draws every slot of the buffer, the dead particles are discarded in the vertex shader. Render it after the
+    // opaque geometry, or from `WindowHandler::render_oit` with `ParticleBlend::Oit`
+    pub fn render<'a: 'b, 'b>(&'a self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass<'b>) {
+        self.shader.bind(surface_ctx, render_pass);
+        render_pass.set_bind_group(0, &self.camera, &[]);
+        render_pass.set_bind_group(1, &self.settings.binding, &[]);
+        render_pass.set_bind_group(2, &self.sprite.binding, &[]);
+        self.quad.render_instances(render_pass, &self.particles, 0..self.capacity);
+    }
+}
@@ -0,0 +1,6 @@
+#include "buildins/particles_vertex.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sprite_texture, sprite_sampler, in.tex_coords) * in.color;
+}
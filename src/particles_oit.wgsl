@@ -0,0 +1,7 @@
+#include "buildins/particles_vertex.wgsl"
+#include "buildins/oit.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) -> OitOutput {
+    return oit_output(textureSample(sprite_texture, sprite_sampler, in.tex_coords) * in.color, in.clip_position.z);
+}
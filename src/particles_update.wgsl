@@ -0,0 +1,77 @@
+#include "buildins/particle_types.wgsl"
+
+particles: $0,0;
+emitter: $1;
+frame: $2;
+
+struct ParticleFrame {
+    delta_time: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+};
+
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967295.0;
+}
+
+fn spawn(index: u32) -> Particle {
+    var state = hash(index ^ hash(frame.seed));
+    // a direction inside the cone of half angle `spread` around `direction`
+    let axis = normalize(emitter.direction);
+    let tangent = normalize(select(cross(axis, vec3(0.0, 1.0, 0.0)), cross(axis, vec3(1.0, 0.0, 0.0)), abs(axis.y) > 0.99));
+    let bitangent = cross(axis, tangent);
+    let cos_theta = mix(cos(emitter.spread), 1.0, random(&state));
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 6.2831853 * random(&state);
+    let direction = axis * cos_theta + (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta;
+    let speed = emitter.speed * (1.0 + (random(&state) * 2.0 - 1.0) * emitter.speed_variance);
+    // a point inside the sphere of `radius` around `position`
+    let z = random(&state) * 2.0 - 1.0;
+    let a = 6.2831853 * random(&state);
+    let r = sqrt(max(0.0, 1.0 - z * z));
+    let offset = vec3(r * cos(a), r * sin(a), z) * emitter.radius * pow(random(&state), 1.0 / 3.0);
+    var particle: Particle;
+    particle.position = emitter.position + offset;
+    particle.velocity = direction * speed;
+    particle.age = 0.0;
+    particle.lifetime = max(emitter.lifetime * (1.0 + (random(&state) * 2.0 - 1.0) * emitter.lifetime_variance), 1e-3);
+    return particle;
+}
+
+fn integrate(particle: Particle) -> Particle {
+    let dt = frame.delta_time;
+    var out = particle;
+    out.velocity = (out.velocity + emitter.gravity * dt) / (1.0 + emitter.drag * dt);
+    out.position += out.velocity * dt;
+    out.age += dt;
+    if emitter.collide != 0u && out.position.y < emitter.ground_height {
+        out.position.y = emitter.ground_height;
+        if out.velocity.y < 0.0 {
+            out.velocity = vec3(out.velocity.x, -out.velocity.y, out.velocity.z) * emitter.bounce;
+        }
+    }
+    return out;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let capacity = arrayLength(&particles);
+    let index = global_id.x + global_id.y * groups.x * 64u;
+    if index >= capacity {
+        return;
+    }
+    // the buffer is a ring, new particles go into the slots after the last spawned ones and replace the oldest
+    if (index + capacity - frame.spawn_start) % capacity < frame.spawn_count {
+        particles[index] = spawn(index);
+    } else if particles[index].age < particles[index].lifetime {
+        particles[index] = integrate(particles[index]);
+    }
+}
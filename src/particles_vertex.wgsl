@@ -0,0 +1,45 @@
+// The camera facing quads of a `ParticleSystem`, one instance per particle
+#include "buildins/particle_types.wgsl"
+#include "buildins/billboard_facing.wgsl"
+
+camera: $0;
+emitter: $1;
+sprite_texture: $2,0;
+sprite_sampler: $2,1;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct ParticleInput {
+    @location(5) position: vec3<f32>,
+    @location(6) age: f32,
+    @location(7) velocity: vec3<f32>,
+    @location(8) lifetime: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    particle: ParticleInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // dead particles collapse outside the clip volume
+    if particle.age >= particle.lifetime {
+        out.clip_position = vec4(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    let t = particle.age / particle.lifetime;
+    let world_position = billboard_spherical(particle.position, model.position.xy * emitter_size(t), camera.position);
+    out.clip_position = camera.view_proj * vec4(world_position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.color = emitter_color(t);
+    return out;
+}
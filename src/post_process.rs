@@ -0,0 +1,161 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::{CommandEncoder, Device};
+
+use crate::{binding::{create_layout, Binding, Uniform, UniformBinding}, model::Render, render_target::{RenderTarget, RenderTargetConfig}, shader::{ScenePipeline, Shader}, surface_context::SurfaceCtx, texture::{DepthTexture, Texture, TextureLayoutConfig}};
+
+pub struct PostProcessInputs<'a> {
+    // output of the previous enabled stage, or the scene color for the first one
+    pub previous: &'a UniformBinding<Texture>,
+    pub scene: &'a UniformBinding<Texture>,
+    pub depth: &'a UniformBinding<DepthTexture>,
+}
+
+pub trait PostProcessEffect {
+    fn render(&self, surface_ctx: &dyn SurfaceCtx, encoder: &mut CommandEncoder, inputs: &PostProcessInputs, output: &RenderTarget);
+    fn resize(&mut self, _device: &Device, _width: u32, _height: u32) {}
+}
+
+// a uniform the effect reads its binding from on every render, so it can still be replaced after the effect is made
+pub type SharedUniform = Rc<RefCell<dyn Uniform>>;
+
+// A single full screen pass, the shader gets the previous stage at group 0, the scene color at group 1,
+// the depth texture at group 2 and any extra uniforms from group 3 on. The pipeline follows the scene format and
+// the multisampling of the depth texture
+pub struct ShaderEffect {
+    pub shader: ScenePipeline,
+    pub uniforms: Vec<SharedUniform>,
+}
+
+impl ShaderEffect {
+    pub fn new(resource_path: &str, surface_ctx: &dyn SurfaceCtx, uniforms: Vec<SharedUniform>) -> Self {
+        let resource_path = resource_path.to_string();
+        let shader_uniforms = uniforms.clone();
+        let shader = ScenePipeline::new(surface_ctx, move |device, scene_format, sample_count| {
+            let texture_layout = create_layout::<Texture>(TextureLayoutConfig::default(), device);
+            let texture_type = Texture::shader_type(TextureLayoutConfig::default());
+            let depth_config = || TextureLayoutConfig { sample_count, ..Default::default() };
+            let depth_layout = create_layout::<DepthTexture>(depth_config(), device);
+            let depth_type = DepthTexture::shader_type(depth_config());
+            let uniforms = shader_uniforms.iter().map(|uniform| uniform.borrow()).collect::<Vec<_>>();
+            Shader::new_post_process(
+                &resource_path,
+                device,
+                scene_format,
+                [&texture_layout, &texture_layout, &depth_layout].into_iter().chain(uniforms.iter().map(|uniform| uniform.layout())).collect(),
+                [&texture_type, &texture_type, &depth_type].into_iter().chain(uniforms.iter().map(|uniform| uniform.shader_type())).collect(),
+            )
+        });
+        Self { shader, uniforms }
+    }
+}
+
+impl PostProcessEffect for ShaderEffect {
+    fn render(&self, surface_ctx: &dyn SurfaceCtx, encoder: &mut CommandEncoder, inputs: &PostProcessInputs, output: &RenderTarget) {
+        let mut render_pass = output.begin_render_pass(encoder, true);
+        self.shader.bind(surface_ctx, &mut render_pass);
+        render_pass.set_bind_group(0, &inputs.previous.binding, &[]);
+        render_pass.set_bind_group(1, &inputs.scene.binding, &[]);
+        render_pass.set_bind_group(2, &inputs.depth.binding, &[]);
+        for (i, uniform) in self.uniforms.iter().enumerate() {
+            render_pass.set_bind_group(3 + i as u32, uniform.borrow().binding(), &[]);
+        }
+        surface_ctx.screen_model().render(&mut render_pass);
+    }
+}
+
+pub struct PostProcessStage {
+    pub name: String,
+    pub enabled: bool,
+    pub effect: Box<dyn PostProcessEffect>,
+}
+
+pub struct PostProcessChain {
+    pub stages: Vec<PostProcessStage>,
+    targets: Option<[RenderTarget; 2]>,
+}
+
+impl Default for PostProcessChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessChain {
+    pub fn new() -> Self {
+        Self { stages: vec![], targets: None }
+    }
+
+    pub fn push(&mut self, name: &str, effect: impl PostProcessEffect + 'static) {
+        self.stages.push(PostProcessStage { name: name.into(), enabled: true, effect: Box::new(effect) });
+    }
+
+    pub fn insert(&mut self, index: usize, name: &str, effect: impl PostProcessEffect + 'static) {
+        self.stages.insert(index, PostProcessStage { name: name.into(), enabled: true, effect: Box::new(effect) });
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<PostProcessStage> {
+        let index = self.stages.iter().position(|stage| stage.name == name)?;
+        Some(self.stages.remove(index))
+    }
+
+    pub fn stage_mut(&mut self, name: &str) -> Option<&mut PostProcessStage> {
+        self.stages.iter_mut().find(|stage| stage.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if let Some(stage) = self.stage_mut(name) {
+            stage.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.stages.iter().any(|stage| stage.name == name && stage.enabled)
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        self.stages.iter().any(|stage| stage.enabled)
+    }
+
+    fn update_targets(&mut self, device: &Device, input: &Texture) {
+        let (width, height, format) = (input.size.width, input.size.height, input.format);
+        match &mut self.targets {
+            Some(targets) => {
+                for target in targets.iter_mut() {
+                    target.set_formats(device, vec![format]);
+                    target.resize(device, width, height);
+                }
+            }
+            None => {
+                self.targets = Some([0, 1].map(|_| RenderTarget::new(device, "Post Processing Chain Texture", width, height, RenderTargetConfig {
+                    formats: vec![format],
+                    depth: false,
+                    clear_colors: vec![wgpu::Color::BLACK],
+                    filter_mode: wgpu::FilterMode::Linear,
+                    ..Default::default()
+                })));
+            }
+        }
+        for stage in &mut self.stages {
+            stage.effect.resize(device, width, height);
+        }
+    }
+
+    // Runs every enabled stage, ping-ponging between two cached textures, and returns the final output
+    pub fn run<'s>(&'s mut self, surface_ctx: &'s dyn SurfaceCtx, encoder: &mut CommandEncoder, scene: &'s UniformBinding<Texture>, input: &'s UniformBinding<Texture>) -> &'s UniformBinding<Texture> {
+        if !self.any_enabled() {
+            return input;
+        }
+        self.update_targets(surface_ctx.device(), &input.value);
+        let targets = self.targets.as_ref().unwrap();
+        let mut previous = input;
+        let mut current = 0;
+        for stage in self.stages.iter().filter(|stage| stage.enabled) {
+            let inputs = PostProcessInputs { previous, scene, depth: surface_ctx.depth_texture() };
+            stage.effect.render(surface_ctx, encoder, &inputs, &targets[current]);
+            previous = &targets[current].colors[0];
+            current = 1 - current;
+        }
+        previous
+    }
+}
@@ -0,0 +1,292 @@
+use std::{cell::RefCell, rc::Rc};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::{CommandEncoder, Device, TextureFormat};
+
+use crate::{binding::{create_layout, Binding, UniformBinding, WgslType}, camera::Camera, model::Render, post_process::{PostProcessEffect, PostProcessInputs, ShaderEffect}, render_target::{RenderTarget, RenderTargetConfig}, shader::{ScenePipeline, Shader}, surface_context::SurfaceCtx, texture::{Texture, TextureLayoutConfig}};
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+pub struct TonemapSettings {
+    pub exposure: f32,
+    // 0 = ACES, 1 = Reinhard, 2 = none (only applies exposure)
+    pub mode: u32,
+    pub white_point: f32,
+    pub padding: f32,
+}
+
+impl TonemapSettings {
+    pub const ACES: u32 = 0;
+    pub const REINHARD: u32 = 1;
+    pub const NONE: u32 = 2;
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self { exposure: 1.0, mode: Self::ACES, white_point: 4.0, padding: 0.0 }
+    }
+}
+
+impl WgslType for TonemapSettings {
+    fn wgsl_name() -> String {
+        "TonemapSettings".into()
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct GammaSettings {
+    pub gamma: f32,
+    pub padding: [f32; 3],
+}
+
+impl Default for GammaSettings {
+    fn default() -> Self {
+        Self { gamma: 2.2, padding: [0.0; 3] }
+    }
+}
+
+impl WgslType for GammaSettings {
+    fn wgsl_name() -> String {
+        "GammaSettings".into()
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct FxaaSettings {
+    pub span_max: f32,
+    pub reduce_min: f32,
+    pub reduce_mul: f32,
+    pub padding: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self { span_max: 8.0, reduce_min: 1.0 / 128.0, reduce_mul: 1.0 / 8.0, padding: 0.0 }
+    }
+}
+
+impl WgslType for FxaaSettings {
+    fn wgsl_name() -> String {
+        "FxaaSettings".into()
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct VignetteSettings {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub radius: f32,
+    pub softness: f32,
+    pub padding: [f32; 2],
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self { color: [0.0; 3], intensity: 0.5, radius: 0.75, softness: 0.45, padding: [0.0; 2] }
+    }
+}
+
+impl WgslType for VignetteSettings {
+    fn wgsl_name() -> String {
+        "VignetteSettings".into()
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct ChromaticAberrationSettings {
+    pub intensity: f32,
+    pub padding: [f32; 3],
+}
+
+impl Default for ChromaticAberrationSettings {
+    fn default() -> Self {
+        Self { intensity: 0.005, padding: [0.0; 3] }
+    }
+}
+
+impl WgslType for ChromaticAberrationSettings {
+    fn wgsl_name() -> String {
+        "ChromaticAberrationSettings".into()
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct FogSettings {
+    // the alpha is used as the maximum amount of fog
+    pub color: [f32; 4],
+    pub density: f32,
+    pub start: f32,
+    pub end: f32,
+    // 0 = linear, 1 = exponential, 2 = exponential squared
+    pub mode: u32,
+}
+
+impl FogSettings {
+    pub const LINEAR: u32 = 0;
+    pub const EXPONENTIAL: u32 = 1;
+    pub const EXPONENTIAL_SQUARED: u32 = 2;
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self { color: [0.6, 0.7, 0.8, 1.0], density: 0.02, start: 10.0, end: 100.0, mode: Self::EXPONENTIAL }
+    }
+}
+
+impl WgslType for FogSettings {
+    fn wgsl_name() -> String {
+        "FogSettings".into()
+    }
+}
+
+pub fn tonemap(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<TonemapSettings>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/tonemap.wgsl", surface_ctx, vec![settings])
+}
+
+pub fn gamma_correction(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<GammaSettings>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/gamma_correction.wgsl", surface_ctx, vec![settings])
+}
+
+pub fn fxaa(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<FxaaSettings>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/fxaa.wgsl", surface_ctx, vec![settings])
+}
+
+pub fn vignette(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<VignetteSettings>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/vignette.wgsl", surface_ctx, vec![settings])
+}
+
+pub fn chromatic_aberration(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<ChromaticAberrationSettings>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/chromatic_aberration.wgsl", surface_ctx, vec![settings])
+}
+
+// Reconstructs the distance to the camera from `SurfaceCtx::depth_texture`, the camera has to be the one the scene was drawn with
+pub fn fog(surface_ctx: &dyn SurfaceCtx, settings: Rc<RefCell<UniformBinding<FogSettings>>>, camera: Rc<RefCell<UniformBinding<Camera>>>) -> ShaderEffect {
+    ShaderEffect::new("buildins/fog.wgsl", surface_ctx, vec![settings, camera])
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+pub struct BloomSettings {
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    // in texels of the smaller mip when upsampling
+    pub filter_radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self { threshold: 1.0, knee: 0.5, intensity: 0.05, filter_radius: 1.0 }
+    }
+}
+
+impl WgslType for BloomSettings {
+    fn wgsl_name() -> String {
+        "BloomSettings".into()
+    }
+}
+
+pub struct Bloom {
+    pub settings: UniformBinding<BloomSettings>,
+    pub levels: u32,
+    prefilter_shader: Shader<'static>,
+    downsample_shader: Shader<'static>,
+    upsample_shader: Shader<'static>,
+    composite_shader: ScenePipeline,
+    downsampled: Vec<RenderTarget>,
+    upsampled: Vec<RenderTarget>,
+    size: (u32, u32),
+}
+
+impl Bloom {
+    const FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(surface_ctx: &dyn SurfaceCtx, settings: BloomSettings, levels: u32) -> Self {
+        let device = surface_ctx.device();
+        let settings = UniformBinding::new(device, "Bloom Settings", settings, None);
+        let texture_layout = create_layout::<Texture>(TextureLayoutConfig::default(), device);
+        let texture_type = Texture::shader_type(TextureLayoutConfig::default());
+        let prefilter_shader = Shader::new_post_process("buildins/bloom_prefilter.wgsl", device, Self::FORMAT, vec![&texture_layout, &settings.layout], vec![&texture_type, &settings.shader_type]);
+        let downsample_shader = Shader::new_post_process("buildins/bloom_downsample.wgsl", device, Self::FORMAT, vec![&texture_layout], vec![&texture_type]);
+        let upsample_shader = Shader::new_post_process("buildins/bloom_upsample.wgsl", device, Self::FORMAT, vec![&texture_layout, &texture_layout, &settings.layout], vec![&texture_type, &texture_type, &settings.shader_type]);
+        let (settings_layout, settings_type) = (settings.layout.clone(), settings.shader_type.clone());
+        // the composite writes the scene format, which changes when HDR is toggled
+        let composite_shader = ScenePipeline::new(surface_ctx, move |device, scene_format, _| {
+            Shader::new_post_process("buildins/bloom_composite.wgsl", device, scene_format, vec![&texture_layout, &texture_layout, &settings_layout], vec![&texture_type, &texture_type, &settings_type])
+        });
+        Self {
+            settings,
+            levels: levels.max(1),
+            prefilter_shader,
+            downsample_shader,
+            upsample_shader,
+            composite_shader,
+            downsampled: vec![],
+            upsampled: vec![],
+            size: (0, 0),
+        }
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings.set_data(queue, settings);
+    }
+
+    fn mip_target(device: &Device, width: u32, height: u32) -> RenderTarget {
+        RenderTarget::new(device, "Bloom Mip", width, height, RenderTargetConfig {
+            formats: vec![Self::FORMAT],
+            depth: false,
+            clear_colors: vec![wgpu::Color::BLACK],
+            filter_mode: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+    }
+}
+
+impl PostProcessEffect for Bloom {
+    fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        if self.size == (width, height) && self.downsampled.len() == self.levels as usize {
+            return;
+        }
+        self.size = (width, height);
+        self.downsampled = (1..=self.levels).map(|level| Self::mip_target(device, (width >> level).max(1), (height >> level).max(1))).collect();
+        self.upsampled = (1..self.levels).map(|level| Self::mip_target(device, (width >> level).max(1), (height >> level).max(1))).collect();
+    }
+
+    fn render(&self, surface_ctx: &dyn SurfaceCtx, encoder: &mut CommandEncoder, inputs: &PostProcessInputs, output: &RenderTarget) {
+        let screen_model = surface_ctx.screen_model();
+        for (i, target) in self.downsampled.iter().enumerate() {
+            let mut render_pass = target.begin_render_pass(encoder, true);
+            if i == 0 {
+                self.prefilter_shader.bind(&mut render_pass);
+                render_pass.set_bind_group(0, &inputs.previous.binding, &[]);
+                render_pass.set_bind_group(1, &self.settings.binding, &[]);
+            } else {
+                self.downsample_shader.bind(&mut render_pass);
+                render_pass.set_bind_group(0, &self.downsampled[i - 1].colors[0].binding, &[]);
+            }
+            screen_model.render(&mut render_pass);
+        }
+        // walk back up the chain, upsampled[i] = upsample(upsampled[i + 1] or the smallest mip) + downsampled[i]
+        for i in (0..self.upsampled.len()).rev() {
+            let low = self.upsampled.get(i + 1).unwrap_or(&self.downsampled[i + 1]);
+            let mut render_pass = self.upsampled[i].begin_render_pass(encoder, true);
+            self.upsample_shader.bind(&mut render_pass);
+            render_pass.set_bind_group(0, &low.colors[0].binding, &[]);
+            render_pass.set_bind_group(1, &self.downsampled[i].colors[0].binding, &[]);
+            render_pass.set_bind_group(2, &self.settings.binding, &[]);
+            screen_model.render(&mut render_pass);
+        }
+        let bloom = self.upsampled.first().unwrap_or(&self.downsampled[0]);
+        let mut render_pass = output.begin_render_pass(encoder, true);
+        self.composite_shader.bind(surface_ctx, &mut render_pass);
+        render_pass.set_bind_group(0, &inputs.previous.binding, &[]);
+        render_pass.set_bind_group(1, &bloom.colors[0].binding, &[]);
+        render_pass.set_bind_group(2, &self.settings.binding, &[]);
+        screen_model.render(&mut render_pass);
+    }
+}
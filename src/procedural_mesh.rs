@@ -0,0 +1,227 @@
+use std::{collections::HashMap, f32::consts::{PI, TAU}};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{mesh::ModelVertex, mesh_processing::{optimize_vertex_fetch, smallest_index_format}, model::{calculate_bounding_box, Model}};
+
+// Generated geometry is centered on the origin with y up, front faces are counter clockwise seen from outside
+// and v goes down like in `BasicVertex::one_face`
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn to_model(&self, device: &wgpu::Device) -> Model {
+        let bounding_box = calculate_bounding_box(&self.vertices);
+        match smallest_index_format(self.vertices.len()) {
+            wgpu::IndexFormat::Uint16 => Model::new(self.vertices.clone(), &self.indices.iter().map(|index| *index as u16).collect::<Vec<_>>(), bounding_box, device),
+            wgpu::IndexFormat::Uint32 => Model::new(self.vertices.clone(), &self.indices, bounding_box, device),
+        }
+    }
+
+    pub fn append(&mut self, other: MeshData) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend(other.vertices);
+        self.indices.extend(other.indices.iter().map(|index| index + offset));
+    }
+
+    // A flat grid on the xz plane facing +y, `subdivisions` is the number of cells along x and z
+    pub fn plane(width: f32, depth: f32, subdivisions: [u32; 2]) -> Self {
+        face(Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z(), 0.0, [width, depth], subdivisions)
+    }
+
+    pub fn cube(size: f32) -> Self {
+        let mut mesh = MeshData::default();
+        let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+        for (normal, right, down) in [(z, x, -y), (-z, -x, -y), (x, -z, -y), (-x, z, -y), (y, x, z), (-y, x, -z)] {
+            mesh.append(face(normal, right, down, size / 2.0, [size, size], [1, 1]));
+        }
+        mesh
+    }
+
+    // `segments` around the y axis, `rings` from pole to pole
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let profile = (0..=rings.max(2)).map(|ring| {
+            let angle = PI * ring as f32 / rings.max(2) as f32;
+            ProfilePoint { radius: radius * angle.sin(), y: radius * angle.cos(), normal: [angle.sin(), angle.cos()] }
+        }).collect::<Vec<_>>();
+        lathe(&profile, segments)
+    }
+
+    // Every subdivision splits each triangle of the icosahedron into 4
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let mut positions = [
+            [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+            [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+            [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+        ].map(|position| Vector3::from(position).normalize()).to_vec();
+        let mut triangles = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vector3<f32>>| *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) / 2.0).normalize());
+                positions.len() as u32 - 1
+            });
+            triangles = triangles.iter().flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(*a, *b, &mut positions), midpoint(*b, *c, &mut positions), midpoint(*c, *a, &mut positions));
+                [[*a, ab, ca], [*b, bc, ab], [*c, ca, bc], [ab, bc, ca]]
+            }).collect();
+        }
+        let uv = |position: Vector3<f32>| [0.5 + position.x.atan2(position.z) / TAU, position.y.acos() / PI];
+        let mut vertices = positions.iter().map(|position| ModelVertex { position: (position * radius).into(), tex_coords: uv(*position), normal: (*position).into() }).collect::<Vec<_>>();
+        let mut wrapped = HashMap::new();
+        for triangle in &mut triangles {
+            let u = triangle.map(|index| vertices[index as usize].tex_coords[0]);
+            // triangles crossing the u seam get copies of their low u vertices moved past 1
+            if u.iter().cloned().fold(f32::MIN, f32::max) - u.iter().cloned().fold(f32::MAX, f32::min) > 0.5 {
+                for index in triangle.iter_mut() {
+                    if vertices[*index as usize].tex_coords[0] < 0.5 {
+                        *index = *wrapped.entry(*index).or_insert_with(|| {
+                            let mut vertex = vertices[*index as usize];
+                            vertex.tex_coords[0] += 1.0;
+                            vertices.push(vertex);
+                            vertices.len() as u32 - 1
+                        });
+                    }
+                }
+            }
+        }
+        // the poles get their own vertex per triangle with u in the middle of the other two
+        for triangle in &mut triangles {
+            for corner in 0..3 {
+                let pole = vertices[triangle[corner] as usize];
+                if pole.normal[1].abs() > 0.9999 {
+                    let others = [triangle[(corner + 1) % 3], triangle[(corner + 2) % 3]].map(|index| vertices[index as usize].tex_coords[0]);
+                    vertices.push(ModelVertex { tex_coords: [(others[0] + others[1]) / 2.0, pole.tex_coords[1]], ..pole });
+                    triangle[corner] = vertices.len() as u32 - 1;
+                }
+            }
+        }
+        // drops the pole vertices that were replaced
+        let (vertices, indices) = optimize_vertex_fetch(&vertices, &triangles.concat());
+        MeshData { vertices, indices }
+    }
+
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height / 2.0;
+        let mut mesh = lathe(&[
+            ProfilePoint { radius, y: half, normal: [1.0, 0.0] },
+            ProfilePoint { radius, y: -half, normal: [1.0, 0.0] },
+        ], segments);
+        mesh.append(cap(radius, half, segments));
+        mesh.append(cap(radius, -half, segments));
+        mesh
+    }
+
+    // The tip points up
+    pub fn cone(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height / 2.0;
+        let slope = Vector3::new(height, radius, 0.0).normalize();
+        let mut mesh = lathe(&[
+            ProfilePoint { radius: 0.0, y: half, normal: [slope.x, slope.y] },
+            ProfilePoint { radius, y: -half, normal: [slope.x, slope.y] },
+        ], segments);
+        mesh.append(cap(radius, -half, segments));
+        mesh
+    }
+
+    // `height` is the length of the cylinder between the two half spheres, `rings` is per half sphere
+    pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Self {
+        let half = height / 2.0;
+        let rings = rings.max(1);
+        let hemisphere = |ring: u32, top: bool| {
+            let angle = PI / 2.0 * ring as f32 / rings as f32 + if top { 0.0 } else { PI / 2.0 };
+            let center = if top { half } else { -half };
+            ProfilePoint { radius: radius * angle.sin(), y: center + radius * angle.cos(), normal: [angle.sin(), angle.cos()] }
+        };
+        let profile = (0..=rings).map(|ring| hemisphere(ring, true)).chain((0..=rings).map(|ring| hemisphere(ring, false))).collect::<Vec<_>>();
+        lathe(&profile, segments)
+    }
+
+    // Lies on the xz plane, `major_radius` goes to the center of the tube
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Self {
+        let profile = (0..=sides.max(3)).map(|side| {
+            let angle = PI / 2.0 - TAU * side as f32 / sides.max(3) as f32;
+            ProfilePoint { radius: major_radius + minor_radius * angle.cos(), y: minor_radius * angle.sin(), normal: [angle.cos(), angle.sin()] }
+        }).collect::<Vec<_>>();
+        lathe(&profile, segments)
+    }
+}
+
+// A rectangle `offset` along `normal`, `right` and `down` are the directions of u and v seen from the front
+fn face(normal: Vector3<f32>, right: Vector3<f32>, down: Vector3<f32>, offset: f32, size: [f32; 2], subdivisions: [u32; 2]) -> MeshData {
+    let [columns, rows] = subdivisions.map(|it| it.max(1));
+    let mut mesh = MeshData::default();
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+            let position = normal * offset + right * (u - 0.5) * size[0] + down * (v - 0.5) * size[1];
+            mesh.vertices.push(ModelVertex { position: position.into(), tex_coords: [u, v], normal: normal.into() });
+        }
+    }
+    grid_indices(&mut mesh, columns, rows);
+    mesh
+}
+
+// two triangles for every cell of a (columns + 1) * (rows + 1) grid of vertices, skipping the ones that collapsed into a point
+fn grid_indices(mesh: &mut MeshData, columns: u32, rows: u32) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let (b, c, d) = (a + 1, a + columns + 1, a + columns + 2);
+            for triangle in [[a, c, b], [b, c, d]] {
+                let [p0, p1, p2] = triangle.map(|index| Vector3::from(mesh.vertices[index as usize].position));
+                if (p1 - p0).cross(p2 - p0).magnitude2() > f32::EPSILON * f32::EPSILON {
+                    mesh.indices.extend(triangle);
+                }
+            }
+        }
+    }
+}
+
+struct ProfilePoint {
+    // distance from the y axis
+    radius: f32,
+    y: f32,
+    // [outwards, up]
+    normal: [f32; 2],
+}
+
+// Spins a profile going from top to bottom around the y axis, u goes around and v along the profile
+fn lathe(profile: &[ProfilePoint], segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let mut mesh = MeshData::default();
+    for (row, point) in profile.iter().enumerate() {
+        for column in 0..=segments {
+            let u = column as f32 / segments as f32;
+            let (sin, cos) = (TAU * u).sin_cos();
+            mesh.vertices.push(ModelVertex {
+                position: [point.radius * sin, point.y, point.radius * cos],
+                tex_coords: [u, row as f32 / (profile.len() - 1) as f32],
+                normal: [point.normal[0] * sin, point.normal[1], point.normal[0] * cos],
+            });
+        }
+    }
+    grid_indices(&mut mesh, segments, profile.len() as u32 - 1);
+    mesh
+}
+
+// A disc facing up when `y` is positive and down otherwise, with the texture projected from above
+fn cap(radius: f32, y: f32, segments: u32) -> MeshData {
+    let up = if y >= 0.0 { 1.0 } else { -1.0 };
+    let rim = ProfilePoint { radius, y, normal: [0.0, up] };
+    let center = ProfilePoint { radius: 0.0, y, normal: [0.0, up] };
+    let mut mesh = lathe(&if up > 0.0 { [center, rim] } else { [rim, center] }, segments);
+    for vertex in &mut mesh.vertices {
+        vertex.tex_coords = [0.5 + vertex.position[0] / (2.0 * radius), 0.5 + vertex.position[2] / (2.0 * radius)];
+    }
+    mesh
+}
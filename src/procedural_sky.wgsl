@@ -0,0 +1,100 @@
+#include "buildins/sky_common.wgsl"
+
+camera: $0;
+sky: $1;
+
+// in meters, the viewer stands 1km above the ground
+const EARTH_RADIUS: f32 = 6371e3;
+const ATMOSPHERE_RADIUS: f32 = 6471e3;
+const VIEWER_HEIGHT: f32 = 1e3;
+const RAYLEIGH: vec3<f32> = vec3(5.5e-6, 13.0e-6, 22.4e-6);
+const RAYLEIGH_HEIGHT: f32 = 8e3;
+const MIE: f32 = 21e-6;
+const MIE_HEIGHT: f32 = 1.2e3;
+const MIE_G: f32 = 0.758;
+const VIEW_STEPS: i32 = 16;
+const LIGHT_STEPS: i32 = 8;
+
+fn sun_disk(direction: vec3<f32>, to_sun: vec3<f32>) -> f32 {
+    let angle = acos(clamp(dot(direction, to_sun), -1.0, 1.0));
+    return 1.0 - smoothstep(sky.sun_size * 0.8, sky.sun_size, angle);
+}
+
+// near and far distance along the ray, far < near if it misses
+fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> vec2<f32> {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    let d = b * b - c;
+    if d < 0.0 {
+        return vec2(1e5, -1e5);
+    }
+    let s = sqrt(d);
+    return vec2(-b - s, -b + s);
+}
+
+fn gradient_sky(direction: vec3<f32>, to_sun: vec3<f32>) -> vec3<f32> {
+    let height = direction.y;
+    if height < 0.0 {
+        return mix(sky.horizon_color, sky.ground_color, sqrt(min(-height * 4.0, 1.0)));
+    }
+    let color = mix(sky.horizon_color, sky.zenith_color, sqrt(height));
+    let glow = pow(max(dot(direction, to_sun), 0.0), 64.0) * 0.05;
+    return color + vec3(sun_disk(direction, to_sun) + glow) * sky.sun_intensity;
+}
+
+// Single scattering through a Rayleigh and Mie atmosphere, the ground below the horizon gets `ground_color`
+fn atmosphere_sky(view: vec3<f32>, to_sun: vec3<f32>) -> vec3<f32> {
+    let direction = normalize(vec3(view.x, max(view.y, 0.0), view.z));
+    let origin = vec3(0.0, EARTH_RADIUS + VIEWER_HEIGHT, 0.0);
+    let far = ray_sphere(origin, direction, ATMOSPHERE_RADIUS).y;
+    let step_size = far / f32(VIEW_STEPS);
+    var rayleigh = vec3(0.0);
+    var mie = vec3(0.0);
+    var depth_rayleigh = 0.0;
+    var depth_mie = 0.0;
+    for (var i = 0; i < VIEW_STEPS; i++) {
+        let position = origin + direction * step_size * (f32(i) + 0.5);
+        let height = length(position) - EARTH_RADIUS;
+        let step_rayleigh = exp(-height / RAYLEIGH_HEIGHT) * step_size;
+        let step_mie = exp(-height / MIE_HEIGHT) * step_size;
+        depth_rayleigh += step_rayleigh;
+        depth_mie += step_mie;
+        let light_step = ray_sphere(position, to_sun, ATMOSPHERE_RADIUS).y / f32(LIGHT_STEPS);
+        var light_rayleigh = 0.0;
+        var light_mie = 0.0;
+        for (var j = 0; j < LIGHT_STEPS; j++) {
+            let light_height = length(position + to_sun * light_step * (f32(j) + 0.5)) - EARTH_RADIUS;
+            light_rayleigh += exp(-light_height / RAYLEIGH_HEIGHT) * light_step;
+            light_mie += exp(-light_height / MIE_HEIGHT) * light_step;
+        }
+        let attenuation = exp(-(RAYLEIGH * (depth_rayleigh + light_rayleigh) + MIE * 1.1 * (depth_mie + light_mie)));
+        rayleigh += step_rayleigh * attenuation;
+        mie += step_mie * attenuation;
+    }
+    let mu = dot(direction, to_sun);
+    let g2 = MIE_G * MIE_G;
+    let phase_rayleigh = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    let phase_mie = 3.0 / (8.0 * PI) * ((1.0 - g2) * (1.0 + mu * mu)) / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5));
+    var color = sky.sun_intensity * (phase_rayleigh * RAYLEIGH * rayleigh + phase_mie * MIE * mie);
+    // the sun disk is dimmed by everything between it and the viewer
+    let transmittance = exp(-(RAYLEIGH * depth_rayleigh + MIE * 1.1 * depth_mie));
+    color += transmittance * sun_disk(direction, to_sun) * sky.sun_intensity;
+    if view.y < 0.0 {
+        let sun_height = max(to_sun.y, 0.0);
+        color = mix(color, sky.ground_color * sun_height * sky.sun_intensity * 0.05, sqrt(min(-view.y * 4.0, 1.0)));
+    }
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = view_direction(in.ndc);
+    let to_sun = -normalize(sky.sun_direction);
+    var color: vec3<f32>;
+    if sky.mode == 1u {
+        color = atmosphere_sky(direction, to_sun);
+    } else {
+        color = gradient_sky(direction, to_sun);
+    }
+    return vec4(color * sky.intensity, 1.0);
+}
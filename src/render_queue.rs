@@ -0,0 +1,100 @@
+use cgmath::{MetricSpace, Vector3};
+use wgpu::RenderPass;
+
+use crate::{shader::BindPipeline, surface_context::SurfaceCtx};
+
+type Draw<'a> = Box<dyn FnOnce(&mut RenderPass<'a>) + 'a>;
+
+// Collects the draws of a frame so they don't have to be made in a particular order. Opaque draws are grouped by
+// pipeline so each one is bound once, transparent ones are drawn after them from back to front. The draw closures
+// set their own bind groups and buffers, the queue binds the pipeline, which is a `Shader` or a `ScenePipeline`
+#[derive(Default)]
+pub struct RenderQueue<'a> {
+    opaque: Vec<(&'a dyn BindPipeline, Draw<'a>)>,
+    // the position is what the distance to the eye is measured from
+    transparent: Vec<(&'a dyn BindPipeline, Vector3<f32>, Draw<'a>)>,
+}
+
+// the address of the pipeline without the vtable, which can differ for the same value
+fn pipeline_id(pipeline: &dyn BindPipeline) -> usize {
+    pipeline as *const dyn BindPipeline as *const () as usize
+}
+
+// The indices of `positions` from the furthest from `eye` to the closest, equally far ones keep their order
+pub fn back_to_front(positions: &[Vector3<f32>], eye: Vector3<f32>) -> Vec<usize> {
+    let mut order = (0..positions.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| positions[*b].distance2(eye).total_cmp(&positions[*a].distance2(eye)));
+    order
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_opaque(&mut self, pipeline: &'a dyn BindPipeline, draw: impl FnOnce(&mut RenderPass<'a>) + 'a) {
+        self.opaque.push((pipeline, Box::new(draw)));
+    }
+
+    pub fn push_transparent(&mut self, pipeline: &'a dyn BindPipeline, position: Vector3<f32>, draw: impl FnOnce(&mut RenderPass<'a>) + 'a) {
+        self.transparent.push((pipeline, position, Box::new(draw)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.transparent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn draw_all(surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass<'a>, draws: impl Iterator<Item = (&'a dyn BindPipeline, Draw<'a>)>) {
+        let mut bound = None;
+        for (pipeline, draw) in draws {
+            if bound != Some(pipeline_id(pipeline)) {
+                pipeline.bind_pipeline(surface_ctx, render_pass);
+                bound = Some(pipeline_id(pipeline));
+            }
+            draw(render_pass);
+        }
+    }
+
+    // Draws and removes the opaque draws, the ones sharing a pipeline keep the order they were pushed in
+    pub fn render_opaque(&mut self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass<'a>) {
+        let mut opaque = std::mem::take(&mut self.opaque);
+        opaque.sort_by_key(|(pipeline, _)| pipeline_id(*pipeline));
+        Self::draw_all(surface_ctx, render_pass, opaque.into_iter());
+    }
+
+    // Draws and removes the transparent draws, the furthest from `eye` first
+    pub fn render_transparent(&mut self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass<'a>, eye: Vector3<f32>) {
+        let transparent = std::mem::take(&mut self.transparent);
+        let order = back_to_front(&transparent.iter().map(|(_, position, _)| *position).collect::<Vec<_>>(), eye);
+        let mut draws = transparent.into_iter().map(|(pipeline, _, draw)| Some((pipeline, draw))).collect::<Vec<_>>();
+        Self::draw_all(surface_ctx, render_pass, order.into_iter().filter_map(|i| draws[i].take()));
+    }
+
+    pub fn render(mut self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass<'a>, eye: Vector3<f32>) {
+        self.render_opaque(surface_ctx, render_pass);
+        self.render_transparent(surface_ctx, render_pass, eye);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::back_to_front;
+
+    #[test]
+    fn transparent_draws_go_back_to_front() {
+        let positions = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 3.0), Vector3::new(4.0, 0.0, 0.0)];
+        assert_eq!(back_to_front(&positions, Vector3::new(0.0, 0.0, 0.0)), [1, 3, 2, 0]);
+        // from the other side the order flips
+        assert_eq!(back_to_front(&positions, Vector3::new(0.0, 0.0, 10.0)), [3, 0, 2, 1]);
+        // equally far draws keep the order they were pushed in
+        let ring = [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0)];
+        assert_eq!(back_to_front(&ring, Vector3::new(0.0, 0.0, 0.0)), [3, 0, 1, 2]);
+        assert!(back_to_front(&[], Vector3::new(0.0, 0.0, 0.0)).is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use wgpu::{CommandEncoder, Device, RenderPass, TextureFormat};
+
+use crate::{binding::{Uniform, UniformBinding}, texture::{DepthTexture, Texture}};
+
+pub struct RenderTargetConfig {
+    pub formats: Vec<TextureFormat>,
+    pub depth: bool,
+    pub sample_count: u32,
+    pub clear_colors: Vec<wgpu::Color>,
+    pub clear_depth: f32,
+    pub filter_mode: wgpu::FilterMode,
+}
+
+impl Default for RenderTargetConfig {
+    fn default() -> Self {
+        Self { formats: vec![TextureFormat::Rgba8UnormSrgb], depth: true, sample_count: 1, clear_colors: vec![], clear_depth: 1.0, filter_mode: wgpu::FilterMode::Nearest }
+    }
+}
+
+pub struct RenderTarget {
+    pub label: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub formats: Vec<TextureFormat>,
+    pub sample_count: u32,
+    // the single sampled textures, these are what later passes read from
+    pub colors: Vec<UniformBinding<Texture>>,
+    // only used when sample_count > 1, they get resolved into `colors` at the end of every pass
+    pub multisample_colors: Vec<Texture>,
+    pub depth: Option<UniformBinding<DepthTexture>>,
+    pub clear_colors: Vec<wgpu::Color>,
+    pub clear_depth: f32,
+    pub filter_mode: wgpu::FilterMode,
+}
+
+impl RenderTarget {
+    pub fn new(device: &Device, label: &'static str, width: u32, height: u32, config: RenderTargetConfig) -> Self {
+        let mut target = Self {
+            label,
+            width: width.max(1),
+            height: height.max(1),
+            formats: config.formats,
+            sample_count: config.sample_count.max(1),
+            colors: vec![],
+            multisample_colors: vec![],
+            depth: None,
+            clear_colors: config.clear_colors,
+            clear_depth: config.clear_depth,
+            filter_mode: config.filter_mode,
+        };
+        target.allocate(device, config.depth);
+        target
+    }
+
+    fn allocate(&mut self, device: &Device, depth: bool) {
+        self.colors = self.formats.iter().map(|format| {
+            let texture = Texture::blank_texture(device, self.width, self.height, *format, 1).with_sampler(device, self.filter_mode, wgpu::AddressMode::ClampToEdge);
+            UniformBinding::new(device, self.label, texture, None)
+        }).collect();
+        self.multisample_colors = if self.sample_count > 1 {
+            self.formats.iter().map(|format| Texture::blank_texture(device, self.width, self.height, *format, self.sample_count)).collect()
+        } else {
+            vec![]
+        };
+        self.depth = if depth {
+            Some(UniformBinding::new(device, self.label, DepthTexture::create_depth_texture(device, self.width, self.height, self.label, self.sample_count), None))
+        } else {
+            None
+        };
+    }
+
+    // Returns true if the textures had to be recreated
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) -> bool {
+        let (width, height) = (width.max(1), height.max(1));
+        if width == self.width && height == self.height {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.allocate(device, self.depth.is_some());
+        true
+    }
+
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) -> bool {
+        let sample_count = sample_count.max(1);
+        if sample_count == self.sample_count {
+            return false;
+        }
+        self.sample_count = sample_count;
+        self.allocate(device, self.depth.is_some());
+        true
+    }
+
+    pub fn set_formats(&mut self, device: &Device, formats: Vec<TextureFormat>) -> bool {
+        if formats == self.formats {
+            return false;
+        }
+        self.formats = formats;
+        self.allocate(device, self.depth.is_some());
+        true
+    }
+
+    pub fn clear_color(&self, index: usize) -> wgpu::Color {
+        self.clear_colors.get(index).copied().unwrap_or(wgpu::Color::TRANSPARENT)
+    }
+
+    pub fn color(&self, index: usize) -> &Texture {
+        &self.colors[index].value
+    }
+
+    pub fn depth_texture(&self) -> Option<&DepthTexture> {
+        self.depth.as_ref().map(|depth| &depth.value)
+    }
+
+    // Every color texture followed by the depth texture, in the order they should be passed to `Shader::new_uniform`
+    pub fn uniforms(&self) -> Vec<&dyn Uniform> {
+        let mut uniforms = self.colors.iter().map(|color| color as &dyn Uniform).collect::<Vec<_>>();
+        if let Some(depth) = &self.depth {
+            uniforms.push(depth);
+        }
+        uniforms
+    }
+
+    pub fn begin_render_pass<'e>(&'e self, encoder: &'e mut CommandEncoder, clear: bool) -> RenderPass<'e> {
+        let color_attachments = self.colors.iter().enumerate().map(|(i, color)| {
+            let multisample_color = self.multisample_colors.get(i);
+            Some(wgpu::RenderPassColorAttachment {
+                view: multisample_color.map(|it| &it.view).unwrap_or(&color.value.view),
+                resolve_target: multisample_color.map(|_| &color.value.view),
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(self.clear_color(i)) } else { wgpu::LoadOp::Load },
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        }).collect::<Vec<_>>();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &color_attachments,
+            multiview_mask: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            depth_stencil_attachment: self.depth.as_ref().map(|depth| wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.value.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(self.clear_depth) } else { wgpu::LoadOp::Load },
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    // Passes resolve automatically, this is only needed when the multisampled textures were written some other way
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        if self.multisample_colors.is_empty() {
+            return;
+        }
+        self.begin_render_pass(encoder, false);
+    }
+}
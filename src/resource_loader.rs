@@ -1,8 +1,10 @@
 use core::str;
-use std::{env, fs::{read_dir, File}, io::{BufWriter, Read, Write}, path::{Path, PathBuf}, sync::Mutex};
+use std::{env, fs::{read_dir, File}, io::{BufWriter, Read, Write}, path::{Component, Path, PathBuf}, sync::Mutex};
 
 use phf_codegen::Map;
 
+use crate::{baked_mesh::BakedMesh, mesh_processing::MeshProcessingOptions};
+
 pub static GLOBAL_PROJECT_RESOURCES: Mutex<Option<&phf::Map<&'static str, ResourceType>>> = Mutex::new(None);
 
 pub fn load_resource(path: &str) -> Option<Vec<u8>> {
@@ -24,7 +26,21 @@ pub fn load_resource_string(path: &str) -> String {
     String::from_utf8(load_resource(path).unwrap()).unwrap()
 }
 
+pub fn resource_paths(prefix: &str) -> Vec<String> {
+    let prefix = prefix.replace("\\", "/");
+    let resources = GLOBAL_PROJECT_RESOURCES.lock().unwrap();
+    let Some(resources) = resources.as_ref() else { return vec![]; };
+    let mut paths = resources.keys().filter(|path| path.starts_with(&prefix)).map(|path| path.to_string()).collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
 pub fn generate_resources(res_dir: &Path, dynamic: bool) {
+    generate_resources_with(res_dir, dynamic, None);
+}
+
+// With `bake_meshes` every .obj also gets baked into a .bmesh next to it in the resources, see `MeshModel::load_baked`
+pub fn generate_resources_with(res_dir: &Path, dynamic: bool, bake_meshes: Option<&MeshProcessingOptions>) {
     let res_dir = &workspace_dir().as_path().join(res_dir);
     let path = Path::new(&env::var("OUT_DIR").unwrap()).join("resources.rs");
     let mut file = BufWriter::new(File::create(&path).unwrap());
@@ -46,6 +62,9 @@ pub fn generate_resources(res_dir: &Path, dynamic: bool) {
                     } else {
                         resources.entry(src_relative_path.clone().replace("\\", "/"), format!("bespoke_engine::resource_loader::ResourceType::Static(include_bytes!(r#\"{path_string}\"#))"));
                     }
+                    if let Some(options) = bake_meshes.filter(|_| path.extension().is_some_and(|extension| extension == "obj")) {
+                        bake_mesh(&mut resources, &src_relative_path.replace("\\", "/"), options, dynamic);
+                    }
                 }
             }
         }
@@ -53,6 +72,36 @@ pub fn generate_resources(res_dir: &Path, dynamic: bool) {
     buildin_resource(&mut resources, "buildins/culling.wgsl", include_bytes!("culling.wgsl"));
     buildin_resource(&mut resources, "buildins/global_shader_types.wgsl", include_bytes!("global_shader_types.wgsl"));
     buildin_resource(&mut resources, "buildins/screen_renderer.wgsl", include_bytes!("screen_renderer.wgsl"));
+    buildin_resource(&mut resources, "buildins/tonemap_common.wgsl", include_bytes!("tonemap_common.wgsl"));
+    buildin_resource(&mut resources, "buildins/tonemap.wgsl", include_bytes!("tonemap.wgsl"));
+    buildin_resource(&mut resources, "buildins/gamma_correction.wgsl", include_bytes!("gamma_correction.wgsl"));
+    buildin_resource(&mut resources, "buildins/fxaa.wgsl", include_bytes!("fxaa.wgsl"));
+    buildin_resource(&mut resources, "buildins/vignette.wgsl", include_bytes!("vignette.wgsl"));
+    buildin_resource(&mut resources, "buildins/chromatic_aberration.wgsl", include_bytes!("chromatic_aberration.wgsl"));
+    buildin_resource(&mut resources, "buildins/fog.wgsl", include_bytes!("fog.wgsl"));
+    buildin_resource(&mut resources, "buildins/bloom_prefilter.wgsl", include_bytes!("bloom_prefilter.wgsl"));
+    buildin_resource(&mut resources, "buildins/bloom_downsample.wgsl", include_bytes!("bloom_downsample.wgsl"));
+    buildin_resource(&mut resources, "buildins/bloom_upsample.wgsl", include_bytes!("bloom_upsample.wgsl"));
+    buildin_resource(&mut resources, "buildins/bloom_composite.wgsl", include_bytes!("bloom_composite.wgsl"));
+    buildin_resource(&mut resources, "buildins/light_types.wgsl", include_bytes!("light_types.wgsl"));
+    buildin_resource(&mut resources, "buildins/lighting.wgsl", include_bytes!("lighting.wgsl"));
+    buildin_resource(&mut resources, "buildins/shadows.wgsl", include_bytes!("shadows.wgsl"));
+    buildin_resource(&mut resources, "buildins/gbuffer.wgsl", include_bytes!("gbuffer.wgsl"));
+    buildin_resource(&mut resources, "buildins/deferred_lighting.wgsl", include_bytes!("deferred_lighting.wgsl"));
+    buildin_resource(&mut resources, "buildins/sky_common.wgsl", include_bytes!("sky_common.wgsl"));
+    buildin_resource(&mut resources, "buildins/skybox_cubemap.wgsl", include_bytes!("skybox_cubemap.wgsl"));
+    buildin_resource(&mut resources, "buildins/skybox_equirectangular.wgsl", include_bytes!("skybox_equirectangular.wgsl"));
+    buildin_resource(&mut resources, "buildins/procedural_sky.wgsl", include_bytes!("procedural_sky.wgsl"));
+    buildin_resource(&mut resources, "buildins/ibl.wgsl", include_bytes!("ibl.wgsl"));
+    buildin_resource(&mut resources, "buildins/ibl_common.wgsl", include_bytes!("ibl_common.wgsl"));
+    buildin_resource(&mut resources, "buildins/oit.wgsl", include_bytes!("oit.wgsl"));
+    buildin_resource(&mut resources, "buildins/oit_composite.wgsl", include_bytes!("oit_composite.wgsl"));
+    buildin_resource(&mut resources, "buildins/billboard_facing.wgsl", include_bytes!("billboard_facing.wgsl"));
+    buildin_resource(&mut resources, "buildins/billboard.wgsl", include_bytes!("billboard.wgsl"));
+    buildin_resource(&mut resources, "buildins/particle_types.wgsl", include_bytes!("particle_types.wgsl"));
+    buildin_resource(&mut resources, "buildins/particles_vertex.wgsl", include_bytes!("particles_vertex.wgsl"));
+    buildin_resource(&mut resources, "buildins/particles.wgsl", include_bytes!("particles.wgsl"));
+    buildin_resource(&mut resources, "buildins/particles_oit.wgsl", include_bytes!("particles_oit.wgsl"));
     write!(
         &mut file,
 "static RESOURCES: phf::Map<&'static str, bespoke_engine::resource_loader::ResourceType> = {};",
@@ -91,6 +140,23 @@ fn buildin_resource(resources: &mut Map<String>, path: &str, bytes: &[u8]) {
     resources.entry(path.into(), format!("bespoke_engine::resource_loader::ResourceType::Static(include_bytes!(r#\"{path_string}\"#))"));
 }
 
+fn bake_mesh(resources: &mut Map<String>, obj_path: &str, options: &MeshProcessingOptions, dynamic: bool) {
+    let src_dir = workspace_dir().join("src");
+    let (baked, _) = BakedMesh::from_obj(Path::new(obj_path), options, |path| std::fs::read(src_dir.join(path)).ok())
+        .unwrap_or_else(|error| panic!("failed to bake {obj_path}: {error}"));
+    let baked_path = Path::new(obj_path).with_extension("bmesh").as_os_str().to_str().unwrap().replace("\\", "/");
+    // the resource path can start with .. so the components that would leave OUT_DIR are dropped
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("baked").join(Path::new(&baked_path).components().filter(|component| matches!(component, Component::Normal(_))).collect::<PathBuf>());
+    std::fs::create_dir_all(out_path.parent().unwrap()).unwrap();
+    baked.save(&out_path).unwrap();
+    let out_string = out_path.as_os_str().to_str().unwrap();
+    if dynamic {
+        resources.entry(baked_path, format!("bespoke_engine::resource_loader::ResourceType::Dynamic(\"{out_string}\")"));
+    } else {
+        resources.entry(baked_path, format!("bespoke_engine::resource_loader::ResourceType::Static(include_bytes!(r#\"{out_string}\"#))"));
+    }
+}
+
 fn workspace_dir() -> PathBuf {
     let output = std::process::Command::new(env!("CARGO"))
         .arg("locate-project")
@@ -0,0 +1,199 @@
+use std::rc::Rc;
+
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::{Buffer, Device, Queue, RenderPass};
+
+use crate::{animation::Transform, billboard::Billboard, camera::Camera, culling::culled, mesh::MeshModel, model::{write_growing, Model, Render, INSTANCE_USAGE}};
+
+pub type NodeId = usize;
+
+#[derive(Clone)]
+pub enum SceneObject {
+    Model(Rc<Model>),
+    MeshModel(Rc<MeshModel>),
+    // drawn with the node transform instead of its own position and rotation
+    Billboard(Rc<Billboard>),
+}
+
+impl SceneObject {
+    fn culled(&self, world: Matrix4<f32>, camera: &Camera) -> bool {
+        match self {
+            SceneObject::Model(model) => culled(model, world, camera),
+            SceneObject::MeshModel(mesh) => mesh.models.iter().all(|model| culled(model, world, camera)),
+            SceneObject::Billboard(billboard) => culled(billboard.model(), world, camera),
+        }
+    }
+
+    fn render_instances<'a: 'b, 'b>(&'a self, render_pass: &mut RenderPass<'b>, instances: &Buffer, instance: u32) {
+        match self {
+            SceneObject::Model(model) => model.render_instances(render_pass, instances, instance..instance + 1),
+            SceneObject::MeshModel(mesh) => mesh.render_instances(render_pass, instances, instance..instance + 1),
+            SceneObject::Billboard(billboard) => billboard.render_instances(render_pass, instances, instance..instance + 1),
+        }
+    }
+}
+
+pub struct SceneNode {
+    pub name: Option<String>,
+    // relative to the parent
+    transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    pub object: Option<SceneObject>,
+    world: Matrix4<f32>,
+    dirty: bool,
+}
+
+impl SceneNode {
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    // only up to date after `Scene::update`
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.world
+    }
+}
+
+// Every node's world matrix is stored in one instance buffer with the layout of `Instance`, at the index of the node,
+// so objects are drawn through `render_instances` and shaders read the transform from the instance attributes
+pub struct Scene {
+    nodes: Vec<Option<SceneNode>>,
+    free: Vec<NodeId>,
+    instance_buffer: Buffer,
+    instances_dirty: bool,
+}
+
+impl Scene {
+    pub fn new(device: &Device) -> Self {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Scene Instance Buffer"),
+            size: 0,
+            usage: INSTANCE_USAGE,
+            mapped_at_creation: false,
+        });
+        Self { nodes: vec![], free: vec![], instance_buffer, instances_dirty: false }
+    }
+
+    pub fn add(&mut self, name: Option<String>, parent: Option<NodeId>, transform: Transform, object: Option<SceneObject>) -> NodeId {
+        let node = SceneNode { name, transform, parent: None, children: vec![], object, world: Matrix4::identity(), dirty: true };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        if let Some(parent) = parent.filter(|parent| self.node(*parent).is_some()) {
+            self.nodes[id].as_mut().unwrap().parent = Some(parent);
+            self.nodes[parent].as_mut().unwrap().children.push(id);
+        }
+        id
+    }
+
+    // Removes the node and everything below it
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(node) = self.nodes.get_mut(id).and_then(|node| node.take()) else { return; };
+        if let Some(parent) = node.parent.and_then(|parent| self.nodes[parent].as_mut()) {
+            parent.children.retain(|child| *child != id);
+        }
+        for child in node.children {
+            // the child's parent is already gone, so it doesn't need unlinking
+            if let Some(child_node) = self.nodes[child].as_mut() {
+                child_node.parent = None;
+            }
+            self.remove(child);
+        }
+        self.free.push(id);
+        self.instances_dirty = true;
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&SceneNode> {
+        self.nodes.get(id).and_then(|node| node.as_ref())
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.nodes.get_mut(id).and_then(|node| node.as_mut())
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|node| node.as_ref().is_some_and(|node| node.name.as_deref() == Some(name)))
+    }
+
+    pub fn roots(&self) -> Vec<NodeId> {
+        self.nodes.iter().enumerate().filter(|(_, node)| node.as_ref().is_some_and(|node| node.parent.is_none())).map(|(id, _)| id).collect()
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        if let Some(node) = self.node_mut(id) {
+            node.transform = transform;
+            node.dirty = true;
+        }
+    }
+
+    // Keeps the local transform, so the node moves along with its new parent
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> anyhow::Result<()> {
+        if self.node(id).is_none() {
+            anyhow::bail!("scene node {id} doesn't exist");
+        }
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == id {
+                anyhow::bail!("scene node {id} can't be parented to its own descendant");
+            }
+            ancestor = self.node(current).ok_or(anyhow::anyhow!("scene node {current} doesn't exist"))?.parent;
+        }
+        let node = self.nodes[id].as_mut().unwrap();
+        let old_parent = std::mem::replace(&mut node.parent, parent);
+        node.dirty = true;
+        if let Some(old_parent) = old_parent.and_then(|old_parent| self.nodes[old_parent].as_mut()) {
+            old_parent.children.retain(|child| *child != id);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent].as_mut().unwrap().children.push(id);
+        }
+        Ok(())
+    }
+
+    // Recomputes the world matrices of dirty nodes and everything below them and uploads them, call it before `render`
+    pub fn update(&mut self, device: &Device, queue: &Queue) {
+        let mut stack = self.roots().into_iter().map(|root| (root, Matrix4::identity(), false)).collect::<Vec<_>>();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = self.nodes[id].as_mut().unwrap();
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.transform.to_matrix();
+                node.dirty = false;
+                self.instances_dirty = true;
+            }
+            stack.extend(node.children.iter().map(|child| (*child, node.world, changed)));
+        }
+        if self.instances_dirty {
+            let matrices = self.nodes.iter().map(|node| node.as_ref().map(|node| node.world).unwrap_or(Matrix4::identity()).into()).collect::<Vec<[[f32; 4]; 4]>>();
+            write_growing(&mut self.instance_buffer, "Scene Instance Buffer", 0, bytemuck::cast_slice(&matrices), false, device, queue);
+            self.instances_dirty = false;
+        }
+    }
+
+    // Draws every node with an object that isn't outside the camera, with the pipeline that's currently set
+    pub fn render<'a: 'b, 'b>(&'a self, render_pass: &mut RenderPass<'b>, camera: &Camera) {
+        for (id, node) in self.nodes.iter().enumerate() {
+            let Some(node) = node else { continue; };
+            let Some(object) = &node.object else { continue; };
+            if !object.culled(node.world, camera) {
+                object.render_instances(render_pass, &self.instance_buffer, id as u32);
+            }
+        }
+    }
+}
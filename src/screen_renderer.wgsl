@@ -1,8 +1,13 @@
+#include "buildins/tonemap_common.wgsl"
+
 @group(0) @binding(0)
 var t_screen: texture_2d<f32>;
 @group(0) @binding(1)
 var s_screen: sampler;
 
+@group(1) @binding(0)
+var<uniform> tonemap: TonemapSettings;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -25,5 +30,6 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_screen, s_screen, in.tex_coords);
+    let color = textureSample(t_screen, s_screen, in.tex_coords);
+    return vec4(apply_tonemap(color.rgb, tonemap), color.a);
 }
\ No newline at end of file
@@ -1,8 +1,8 @@
-use std::sync::Mutex;
+use std::{cell::RefCell, sync::Mutex};
 
 use wgpu::{BindGroupLayout, Device, FrontFace, PipelineCompilationOptions, PipelineLayout, RenderPass, RenderPipeline, ShaderModule, TextureFormat};
 
-use crate::{binding::{Descriptor, Uniform}, resource_loader::load_resource_string, texture::DepthTexture, window::{BasicVertex, MULTISAMPLE_COUNT}};
+use crate::{binding::{Descriptor, Uniform}, resource_loader::load_resource_string, surface_context::SurfaceCtx, texture::DepthTexture, window::{BasicVertex, MULTISAMPLE_COUNT}};
 
 pub static CUSTOM_SHADER_TYPE_SOURCE: Mutex<String> = Mutex::new(String::new());
 
@@ -27,13 +27,7 @@ impl <'a> Shader<'a> {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(parsed_source.clone().into()),
         });
-        let targets = &formats.iter().map(|format| {
-            Some(wgpu::ColorTargetState {
-                format: *format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                write_mask: wgpu::ColorWrites::ALL,
-            })
-        }).collect::<Vec<Option<wgpu::ColorTargetState>>>();
+        let targets = &config.color_targets(&formats);
         let fragment = if !config.depth_only {
             Some(wgpu::FragmentState {
                 module: &shader,
@@ -103,13 +97,7 @@ impl <'a> Shader<'a> {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(parsed_source.clone().into()),
         });
-        let targets = &self.formats.iter().map(|format| {
-            Some(wgpu::ColorTargetState {
-                format: *format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                write_mask: wgpu::ColorWrites::ALL,
-            })
-        }).collect::<Vec<Option<wgpu::ColorTargetState>>>();
+        let targets = &self.config.color_targets(&self.formats);
         let fragment = if !self.config.depth_only {
             Some(wgpu::FragmentState {
                 module: &shader,
@@ -246,6 +234,51 @@ impl <'a> Shader<'a> {
     }
 }
 
+// Something that sets a pipeline on a render pass, so the render queue can take both plain shaders and `ScenePipeline`s
+pub trait BindPipeline {
+    fn bind_pipeline(&self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass);
+}
+
+impl BindPipeline for Shader<'_> {
+    fn bind_pipeline(&self, _surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass) {
+        render_pass.set_pipeline(&self.pipeline);
+    }
+}
+
+type BuildSceneShader = Box<dyn Fn(&Device, TextureFormat, u32) -> Shader<'static>>;
+
+// The shader of a built-in renderer that draws into the scene texture. It gets rebuilt on the first bind after the scene
+// format or multisample count changed, so the renderers follow the scene target without having to be prepared
+pub struct ScenePipeline {
+    // called with the scene format and multisample count
+    build: BuildSceneShader,
+    // the scene format and multisample count the shader was built for
+    built: RefCell<(TextureFormat, u32, Shader<'static>)>,
+}
+
+impl ScenePipeline {
+    pub fn new(surface_ctx: &dyn SurfaceCtx, build: impl Fn(&Device, TextureFormat, u32) -> Shader<'static> + 'static) -> Self {
+        let (format, sample_count) = (surface_ctx.scene_format(), surface_ctx.scene_target().sample_count);
+        let shader = build(surface_ctx.device(), format, sample_count);
+        Self { build: Box::new(build), built: RefCell::new((format, sample_count, shader)) }
+    }
+
+    pub fn bind(&self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass) {
+        let (format, sample_count) = (surface_ctx.scene_format(), surface_ctx.scene_target().sample_count);
+        let mut built = self.built.borrow_mut();
+        if built.0 != format || built.1 != sample_count {
+            *built = (format, sample_count, (self.build)(surface_ctx.device(), format, sample_count));
+        }
+        render_pass.set_pipeline(&built.2.pipeline);
+    }
+}
+
+impl BindPipeline for ScenePipeline {
+    fn bind_pipeline(&self, surface_ctx: &dyn SurfaceCtx, render_pass: &mut RenderPass) {
+        self.bind(surface_ctx, render_pass);
+    }
+}
+
 pub struct ShaderConfig {
     pub background: bool,
     pub line_mode: wgpu::PolygonMode,
@@ -254,6 +287,10 @@ pub struct ShaderConfig {
     pub face_cull: Option<FrontFace>,
     pub depth_compare: wgpu::CompareFunction,
     pub multisample_count: u32,
+    // None writes the color as is
+    pub blend: Option<wgpu::BlendState>,
+    // per color target, overrides `blend` for the targets it covers
+    pub target_blends: Vec<Option<wgpu::BlendState>>,
 }
 
 impl ShaderConfig {
@@ -269,17 +306,28 @@ impl ShaderConfig {
         }
         None
     }
+
+    pub fn color_targets(&self, formats: &[TextureFormat]) -> Vec<Option<wgpu::ColorTargetState>> {
+        formats.iter().enumerate().map(|(i, format)| {
+            Some(wgpu::ColorTargetState {
+                format: *format,
+                blend: self.target_blends.get(i).copied().unwrap_or(self.blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        }).collect()
+    }
 }
 
 impl Default for ShaderConfig {
     fn default() -> Self {
-        Self { background: true, line_mode: wgpu::PolygonMode::Fill, enable_depth_texture: true, depth_only: false, face_cull: Some(FrontFace::Ccw), depth_compare: wgpu::CompareFunction::Less, multisample_count: MULTISAMPLE_COUNT.lock().unwrap().clone() }
+        Self { background: true, line_mode: wgpu::PolygonMode::Fill, enable_depth_texture: true, depth_only: false, face_cull: Some(FrontFace::Ccw), depth_compare: wgpu::CompareFunction::Less, multisample_count: MULTISAMPLE_COUNT.lock().unwrap().clone(), blend: Some(wgpu::BlendState::ALPHA_BLENDING), target_blends: vec![] }
     }
 }
 
 pub fn parse_shader(shader_source: &str, binding_types: &Vec<ShaderType>) -> String {
     let global_types = load_resource_string("buildins/global_shader_types.wgsl");
     let custom_types = CUSTOM_SHADER_TYPE_SOURCE.lock().unwrap().clone();
+    let shader_source = &resolve_includes(shader_source, &mut vec![]);
     let mut parsed = format!(
 "//GLOBAL TYPES
 {global_types}
@@ -317,6 +365,20 @@ pub fn parse_shader(shader_source: &str, binding_types: &Vec<ShaderType>) -> Str
     parsed
 }
 
+// Replaces `#include "resource/path.wgsl"` lines with that resource, every file is only included once
+fn resolve_includes(source: &str, included: &mut Vec<String>) -> String {
+    source.lines().map(|line| {
+        let Some(path) = line.trim().strip_prefix("#include").map(|path| path.trim().trim_matches('"').to_string()) else {
+            return line.to_string();
+        };
+        if included.contains(&path) {
+            return String::new();
+        }
+        included.push(path.clone());
+        resolve_includes(&load_resource_string(&path), included)
+    }).collect::<Vec<_>>().join("\n")
+}
+
 #[derive(Clone)]
 pub struct ShaderType {
     pub var_types: Vec<String>,
@@ -355,4 +417,4 @@ impl ShaderType {
             wgsl_types,
         }
     }
-}
\ No newline at end of file
+}
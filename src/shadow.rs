@@ -0,0 +1,369 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Transform, Vector3};
+use wgpu::{CommandEncoder, Device, Queue, RenderPass, TextureView};
+
+use crate::{binding::{simple_layout_entry, Binding, Resource, UniformBinding, WgslType}, camera::{Camera, CameraRaw, OrthographicCamera, TargetCamera}, shader::{Shader, ShaderConfig, ShaderType}, texture::{create_texture, DepthTexture}};
+
+pub const MAX_SHADOW_LAYERS: usize = 16;
+
+// cgmath builds OpenGL style projections with a depth range of -1 to 1, wgpu clips everything below 0
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+pub struct ShadowConfig {
+    pub resolution: u32,
+    pub layers: u32,
+    // depth bias in the light's clip space and world space offset along the normal, both scaled in the shader
+    pub bias: f32,
+    pub normal_bias: f32,
+    // how far the cascades reach from the camera
+    pub shadow_distance: f32,
+    // 0 splits the cascades evenly, 1 logarithmically
+    pub cascade_lambda: f32,
+    // how far behind a cascade towards a directional light casters are still rendered
+    pub caster_distance: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self { resolution: 2048, layers: 4, bias: 0.0005, normal_bias: 1.5, shadow_distance: 100.0, cascade_lambda: 0.75, caster_distance: 50.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShadowCascade {
+    pub view_proj: [[f32; 4]; 4],
+    pub split: f32,
+    pub bias: f32,
+    pub normal_bias: f32,
+    pub texel_size: f32,
+}
+
+impl WgslType for ShadowCascade {
+    fn wgsl_name() -> String {
+        "ShadowCascade".into()
+    }
+}
+
+// The camera a shadow layer is rendered with, it binds as `Camera` so depth only shaders can use the usual camera struct
+#[derive(Clone)]
+pub enum ShadowCamera {
+    Orthographic(OrthographicCamera),
+    Perspective(TargetCamera),
+}
+
+impl ShadowCamera {
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * match self {
+            ShadowCamera::Orthographic(camera) => camera.build_view_projection_matrix(),
+            ShadowCamera::Perspective(camera) => camera.build_view_projection_matrix(),
+        }
+    }
+
+    pub fn to_raw(&self) -> CameraRaw {
+        let view_proj = self.build_view_projection_matrix();
+        let eye = match self {
+            ShadowCamera::Orthographic(camera) => camera.eye,
+            ShadowCamera::Perspective(camera) => camera.eye,
+        };
+        CameraRaw {
+            view_proj: view_proj.into(),
+            inverse_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()).into(),
+            eye: eye.into(),
+            padding: 0.0,
+        }
+    }
+}
+
+impl Default for ShadowCamera {
+    fn default() -> Self {
+        ShadowCamera::Orthographic(OrthographicCamera { eye: Vector3::unit_y(), near: 0.0, far: 2.0, left: -1.0, right: 1.0, top: 1.0, bottom: -1.0, target: Vector3::new(0.0, 0.0, 0.0) })
+    }
+}
+
+impl Binding for ShadowCamera {
+    type LayoutConfig = ();
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![simple_layout_entry(0)]
+    }
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        vec![Resource::Simple(bytes_of(&self.to_raw()).to_vec())]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["<uniform>".into()],
+            wgsl_types: vec!["Camera".into()],
+        }
+    }
+}
+
+// What lighting shaders bind, shows up as a depth texture array, a comparison sampler and `Shadows`.
+// See shadows.wgsl for the shader side
+#[derive(Clone)]
+pub struct ShadowTextures {
+    pub view: TextureView,
+    pub sampler: wgpu::Sampler,
+    pub cascades: [ShadowCascade; MAX_SHADOW_LAYERS],
+}
+
+impl Binding for ShadowTextures {
+    type LayoutConfig = ();
+    fn layout_config(&self) -> Self::LayoutConfig {}
+
+    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            simple_layout_entry(2),
+        ]
+    }
+
+    fn create_resources<'a>(&'a self) -> Vec<Resource<'a>> {
+        vec![
+            Resource::Bespoke(wgpu::BindingResource::TextureView(&self.view)),
+            Resource::Bespoke(wgpu::BindingResource::Sampler(&self.sampler)),
+            Resource::Simple(bytemuck::cast_slice(&self.cascades).to_vec()),
+        ]
+    }
+
+    fn shader_type(_config: ()) -> ShaderType {
+        ShaderType {
+            var_types: vec!["".into(), "".into(), "<uniform>".into()],
+            wgsl_types: vec!["texture_depth_2d_array".into(), "sampler_comparison".into(), "Shadows".into()],
+        }
+    }
+}
+
+// A layered depth texture that directional and spot lights render into. Layers are assigned with the `set_*` functions,
+// rendered with `render` and hooked up to lights with `LightSet::set_shadow`
+pub struct ShadowMap {
+    pub config: ShadowConfig,
+    pub texture: wgpu::Texture,
+    layer_views: Vec<TextureView>,
+    pub textures: UniformBinding<ShadowTextures>,
+    pub cameras: Vec<UniformBinding<ShadowCamera>>,
+    active: Vec<bool>,
+}
+
+impl ShadowMap {
+    pub fn new(device: &Device, config: ShadowConfig) -> Self {
+        let layers = config.layers.clamp(1, MAX_SHADOW_LAYERS as u32);
+        let config = ShadowConfig { layers, ..config };
+        let texture = create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d { width: config.resolution, height: config.resolution, depth_or_array_layers: layers },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DepthTexture::DEPTH_FORMAT,
+            view_formats: &[DepthTexture::DEPTH_FORMAT],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let layer_views = (0..layers).map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Map Layer"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })).collect();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Map"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering on a comparison sampler blends the results of the 4 nearest texels
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let textures = UniformBinding::new(device, "Shadow Map", ShadowTextures { view, sampler, cascades: [ShadowCascade::default(); MAX_SHADOW_LAYERS] }, None);
+        let cameras = (0..layers).map(|_| UniformBinding::new(device, "Shadow Camera", ShadowCamera::default(), None)).collect();
+        Self { config, texture, layer_views, textures, cameras, active: vec![false; layers as usize] }
+    }
+
+    // The config for depth only shaders that render into the shadow map, without face culling so thin geometry still casts
+    pub fn shader_config() -> ShaderConfig {
+        ShaderConfig { depth_only: true, face_cull: None, multisample_count: 1, ..Default::default() }
+    }
+
+    // Covers a sphere with an orthographic view along `direction`
+    pub fn set_directional(&mut self, layer: usize, direction: Vector3<f32>, center: Vector3<f32>, radius: f32, queue: &Queue) {
+        let camera = self.fit_sphere(direction, center, radius);
+        self.set_layer(layer, camera, 0.0, queue);
+    }
+
+    // Splits the camera's view up to `shadow_distance` into `count` cascades starting at `first_layer` and returns the split distances
+    pub fn set_cascades(&mut self, first_layer: usize, count: usize, direction: Vector3<f32>, camera: &Camera, queue: &Queue) -> Vec<f32> {
+        let far = self.config.shadow_distance.min(camera.zfar);
+        let splits = cascade_splits(camera.znear, far, count, self.config.cascade_lambda);
+        let forward = camera.get_forward_vec().normalize();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+        let tan_y = (Rad::from(Deg(camera.fovy)).0 / 2.0).tan();
+        let tan_x = tan_y * camera.aspect;
+        // cascades are picked by distance to the camera, so a slice also has to cover points that are closer along the view
+        // direction near the edges of the screen
+        let corner_cos = 1.0 / (1.0 + tan_x * tan_x + tan_y * tan_y).sqrt();
+        let mut near = camera.znear;
+        for (i, split) in splits.iter().enumerate() {
+            let corners = [near * corner_cos, *split].iter().flat_map(|distance| {
+                let (width, height) = (distance * tan_x, distance * tan_y);
+                let center = camera.eye + forward * *distance;
+                [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| center + right * width * x + up * height * y)
+            }).collect::<Vec<_>>();
+            let center = corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, corner| sum + corner) / corners.len() as f32;
+            // rounded up so the size doesn't change while the camera turns, which would make the edges shimmer
+            let radius = (corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0, f32::max) * 16.0).ceil() / 16.0;
+            let light_camera = self.fit_sphere(direction, center, radius);
+            self.set_layer(first_layer + i, light_camera, *split, queue);
+            near = *split;
+        }
+        splits
+    }
+
+    // A perspective view covering the outer cone, `range` 0 uses `shadow_distance` as the far plane
+    pub fn set_spot(&mut self, layer: usize, position: Vector3<f32>, direction: Vector3<f32>, outer_angle: f32, range: f32, queue: &Queue) {
+        let direction = usable_direction(direction);
+        let camera = TargetCamera {
+            eye: position,
+            target: position + direction,
+            aspect: 1.0,
+            fovy: Deg::from(Rad(outer_angle * 2.0)).0.min(170.0),
+            znear: 0.05,
+            zfar: if range > 0.0 { range } else { self.config.shadow_distance },
+        };
+        self.set_layer(layer, ShadowCamera::Perspective(camera), 0.0, queue);
+    }
+
+    // Stops rendering a layer
+    pub fn clear_layer(&mut self, layer: usize) {
+        if let Some(active) = self.active.get_mut(layer) {
+            *active = false;
+        }
+    }
+
+    fn fit_sphere(&self, direction: Vector3<f32>, center: Vector3<f32>, radius: f32) -> ShadowCamera {
+        let direction = usable_direction(direction);
+        // moves the center in whole texels of the light view so the shadow edges don't shimmer when the camera moves
+        let texel = radius * 2.0 / self.config.resolution as f32;
+        let light_view = Matrix4::look_at_rh(Point3::new(0.0, 0.0, 0.0), Point3::new(direction.x, direction.y, direction.z), Vector3::unit_y());
+        let mut light_center = light_view.transform_vector(center);
+        light_center.x = (light_center.x / texel).floor() * texel;
+        light_center.y = (light_center.y / texel).floor() * texel;
+        let center = light_view.inverse_transform().unwrap_or(Matrix4::identity()).transform_vector(light_center);
+        let back = radius + self.config.caster_distance;
+        ShadowCamera::Orthographic(OrthographicCamera {
+            eye: center - direction * back,
+            target: center,
+            near: 0.0,
+            far: back + radius,
+            left: -radius,
+            right: radius,
+            top: radius,
+            bottom: -radius,
+        })
+    }
+
+    fn set_layer(&mut self, layer: usize, camera: ShadowCamera, split: f32, queue: &Queue) {
+        if layer >= self.active.len() {
+            return;
+        }
+        let texel_size = 1.0 / self.config.resolution as f32;
+        self.textures.value.cascades[layer] = ShadowCascade {
+            view_proj: camera.build_view_projection_matrix().into(),
+            split,
+            bias: self.config.bias,
+            // a texel in world units so the offset scales with the cascade, for spot lights at a distance of 1 from the light
+            normal_bias: self.config.normal_bias * match &camera {
+                ShadowCamera::Orthographic(camera) => (camera.right - camera.left) * texel_size,
+                ShadowCamera::Perspective(camera) => 2.0 * (Rad::from(Deg(camera.fovy)).0 / 2.0).tan() * texel_size,
+            },
+            texel_size,
+        };
+        if let Some(buffer) = self.textures.buffers.get(&2) {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.textures.value.cascades));
+        }
+        self.cameras[layer].set_data(queue, camera);
+        self.active[layer] = true;
+    }
+
+    pub fn begin_pass<'e>(&'e self, encoder: &'e mut CommandEncoder, layer: usize) -> RenderPass<'e> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            multiview_mask: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.layer_views[layer],
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    // Renders every active layer with a depth only shader that takes the layer's camera at `camera_group`,
+    // `draw` gets the pass and layer and draws the shadow casters
+    pub fn render(&self, encoder: &mut CommandEncoder, shader: &Shader, camera_group: u32, mut draw: impl FnMut(&mut RenderPass, usize)) {
+        for layer in (0..self.active.len()).filter(|layer| self.active[*layer]) {
+            let mut render_pass = self.begin_pass(encoder, layer);
+            shader.bind(&mut render_pass);
+            render_pass.set_bind_group(camera_group, &self.cameras[layer].binding, &[]);
+            draw(&mut render_pass, layer);
+        }
+    }
+}
+
+// `count` distances between `near` and `far`, the last one is `far`
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    let near = near.max(0.001);
+    (1..=count).map(|i| {
+        let fraction = i as f32 / count as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        lambda * logarithmic + (1.0 - lambda) * uniform
+    }).collect()
+}
+
+// the light views are built with y up, which doesn't work for lights pointing straight up or down
+fn usable_direction(direction: Vector3<f32>) -> Vector3<f32> {
+    let direction = direction.normalize();
+    if direction.y.abs() > 0.999 {
+        (direction + Vector3::new(0.0, 0.0, 0.01)).normalize()
+    } else {
+        direction
+    }
+}
@@ -0,0 +1,44 @@
+// Shadow lookups for lights with `shadow_layers` set, pulled into a shader with `#include "buildins/shadows.wgsl"`.
+// The `ShadowMap` textures binding gives the texture array, comparison sampler and `Shadows` to pass in here.
+// Returns 1 for lit and 0 for fully shadowed, `normal` is the normalized world space normal.
+#include "buildins/light_types.wgsl"
+
+fn shadow_cascade_layer(light: Light, shadows: Shadows, distance_to_camera: f32) -> u32 {
+    var layer = light.shadow_layer;
+    // cascades go from near to far, the last one covers everything past the second to last split
+    for (var i = 0u; i + 1u < light.shadow_layers; i++) {
+        if distance_to_camera > shadows.cascades[light.shadow_layer + i].split {
+            layer += 1u;
+        }
+    }
+    return layer;
+}
+
+fn shadow_factor(light: Light, shadows: Shadows, shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, position: vec3f, normal: vec3f, camera_position: vec3f) -> f32 {
+    if light.shadow_layers == 0u {
+        return 1.0;
+    }
+    let layer = shadow_cascade_layer(light, shadows, distance(position, camera_position));
+    let cascade = shadows.cascades[layer];
+    var normal_bias = cascade.normal_bias;
+    if light.kind == 2u {
+        // spot light texels grow with the distance
+        normal_bias *= distance(position, light.position);
+    }
+    let light_clip = cascade.view_proj * vec4f(position + normal * normal_bias, 1.0);
+    let light_ndc = light_clip.xyz / light_clip.w;
+    let uv = light_ndc.xy * vec2f(0.5, -0.5) + 0.5;
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || light_ndc.z > 1.0 {
+        return 1.0;
+    }
+    let depth = light_ndc.z - cascade.bias;
+    // 3x3 PCF, each tap is already bilinearly filtered by the comparison sampler
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2f(f32(x), f32(y)) * cascade.texel_size;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, layer, depth);
+        }
+    }
+    return lit / 9.0;
+}
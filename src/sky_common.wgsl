@@ -0,0 +1,41 @@
+// Shared by the built-in sky shaders, they declare `camera` and `sky` themselves
+
+const PI: f32 = 3.14159265;
+
+struct SkySettings {
+    sun_direction: vec3f,
+    sun_intensity: f32,
+    zenith_color: vec3f,
+    sun_size: f32,
+    horizon_color: vec3f,
+    intensity: f32,
+    ground_color: vec3f,
+    // 0 = gradient, 1 = atmospheric scattering, only used by the procedural sky
+    mode: u32,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// the quad sits on the far plane so only pixels nothing was drawn to pass the depth test
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position.xy, 1.0, 1.0);
+    out.ndc = model.position.xy;
+    return out;
+}
+
+fn view_direction(ndc: vec2<f32>) -> vec3<f32> {
+    let world = camera.inverse_proj * vec4(ndc, 1.0, 1.0);
+    return normalize(world.xyz / world.w - camera.position);
+}
@@ -0,0 +1,117 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupLayout, Device, Queue, RenderPass, TextureFormat};
+
+use crate::{binding::{Descriptor, Uniform, UniformBinding, WgslType}, model::Render, shader::{ScenePipeline, Shader, ShaderConfig, ShaderType}, surface_context::SurfaceCtx, texture::Texture, window::BasicVertex};
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+pub struct SkySettings {
+    // where the sunlight shines like `Light::Directional`, so the same vector can be used for both
+    pub sun_direction: [f32; 3],
+    pub sun_intensity: f32,
+    pub zenith_color: [f32; 3],
+    // angular radius of the sun disk in radians
+    pub sun_size: f32,
+    pub horizon_color: [f32; 3],
+    // multiplies the whole sky, textured skies only use this
+    pub intensity: f32,
+    pub ground_color: [f32; 3],
+    // 0 = gradient, 1 = atmospheric scattering
+    pub mode: u32,
+}
+
+impl SkySettings {
+    pub const GRADIENT: u32 = 0;
+    pub const ATMOSPHERE: u32 = 1;
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            sun_direction: [-0.3, -0.6, -0.5],
+            sun_intensity: 20.0,
+            zenith_color: [0.1, 0.3, 0.7],
+            sun_size: 0.02,
+            horizon_color: [0.6, 0.75, 0.9],
+            intensity: 1.0,
+            ground_color: [0.25, 0.22, 0.2],
+            mode: Self::GRADIENT,
+        }
+    }
+}
+
+impl WgslType for SkySettings {
+    fn wgsl_name() -> String {
+        "SkySettings".into()
+    }
+}
+
+pub enum SkySource {
+    // see `Texture::cubemap_from_images`
+    Cubemap(Texture),
+    // a 2:1 longitude/latitude panorama
+    Equirectangular(Texture),
+    // drawn from the gradient or atmosphere in `SkySettings`
+    Procedural,
+}
+
+// Draws the sky behind everything else in the scene texture. It sits on the far plane with a `LessEqual` depth test and
+// no depth write, so render it from `WindowHandler::render` after the opaque geometry. The shaders get the camera at
+// group 0, the settings at 1 and the texture at 2
+pub struct Skybox {
+    pub settings: UniformBinding<SkySettings>,
+    texture: Option<UniformBinding<Texture>>,
+    camera: BindGroup,
+    shader: ScenePipeline,
+}
+
+impl Skybox {
+    pub fn new(surface_ctx: &dyn SurfaceCtx, source: SkySource, settings: SkySettings, camera: &dyn Uniform) -> Self {
+        let device = surface_ctx.device();
+        let settings = UniformBinding::new(device, "Sky Settings", settings, None);
+        let (path, texture) = match source {
+            SkySource::Cubemap(texture) => ("buildins/skybox_cubemap.wgsl", Some(texture)),
+            SkySource::Equirectangular(texture) => ("buildins/skybox_equirectangular.wgsl", Some(texture)),
+            SkySource::Procedural => ("buildins/procedural_sky.wgsl", None),
+        };
+        let texture = texture.map(|texture| UniformBinding::new(device, "Sky Texture", texture, None));
+        let mut uniforms: Vec<&dyn Uniform> = vec![camera, &settings];
+        if let Some(texture) = &texture {
+            uniforms.push(texture);
+        }
+        let layouts = uniforms.iter().map(|uniform| uniform.layout().clone()).collect::<Vec<_>>();
+        let shader_types = uniforms.iter().map(|uniform| uniform.shader_type().clone()).collect::<Vec<_>>();
+        Self {
+            shader: ScenePipeline::new(surface_ctx, move |device, scene_format, sample_count| Self::shader(device, path, scene_format, sample_count, &layouts, &shader_types)),
+            camera: camera.binding().clone(),
+            settings,
+            texture,
+        }
+    }
+
+    fn shader(device: &Device, path: &str, scene_format: TextureFormat, sample_count: u32, layouts: &[BindGroupLayout], shader_types: &[ShaderType]) -> Shader<'static> {
+        Shader::new(
+            path,
+            device,
+            vec![scene_format],
+            layouts.iter().collect(),
+            shader_types.iter().collect(),
+            vec![BasicVertex::desc()],
+            ShaderConfig { background: false, face_cull: None, depth_compare: wgpu::CompareFunction::LessEqual, multisample_count: sample_count, ..Default::default() },
+        )
+    }
+
+    pub fn set_settings(&mut self, queue: &Queue, settings: SkySettings) {
+        self.settings.set_data(queue, settings);
+    }
+
+    pub fn render<'a: 'b, 'b>(&'a self, surface_ctx: &'a dyn SurfaceCtx, render_pass: &mut RenderPass<'b>) {
+        self.shader.bind(surface_ctx, render_pass);
+        render_pass.set_bind_group(0, &self.camera, &[]);
+        render_pass.set_bind_group(1, &self.settings.binding, &[]);
+        if let Some(texture) = &self.texture {
+            render_pass.set_bind_group(2, &texture.binding, &[]);
+        }
+        surface_ctx.screen_model().render(render_pass);
+    }
+}
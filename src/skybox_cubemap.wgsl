@@ -0,0 +1,13 @@
+#include "buildins/sky_common.wgsl"
+
+camera: $0;
+sky: $1;
+sky_texture: $2,0;
+sky_sampler: $2,1;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = view_direction(in.ndc);
+    let color = textureSample(sky_texture, sky_sampler, direction).rgb;
+    return vec4(color * sky.intensity, 1.0);
+}
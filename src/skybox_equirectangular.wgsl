@@ -0,0 +1,15 @@
+#include "buildins/sky_common.wgsl"
+
+camera: $0;
+sky: $1;
+sky_texture: $2,0;
+sky_sampler: $2,1;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = view_direction(in.ndc);
+    // longitude around y and latitude from the top, sampled at level 0 so the seam doesn't pick a tiny mip
+    let uv = vec2(atan2(direction.z, direction.x) / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    let color = textureSampleLevel(sky_texture, sky_sampler, uv, 0.0).rgb;
+    return vec4(color * sky.intensity, 1.0);
+}
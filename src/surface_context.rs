@@ -1,15 +1,16 @@
 use std::sync::Arc;
 
-use wgpu::{Device, Queue, SurfaceConfiguration};
+use wgpu::{Device, Queue, SurfaceConfiguration, TextureFormat};
 use winit::window::{Window, WindowId};
 
-use crate::{binding::UniformBinding, model::Model, shader::Shader, texture::DepthTexture};
+use crate::{binding::UniformBinding, model::Model, post_process_effects::TonemapSettings, render_target::RenderTarget, shader::Shader, texture::DepthTexture, window::MULTISAMPLE_COUNT};
 
 pub struct SurfaceContext<'a> {
     pub surface: Arc<wgpu::Surface<'a>>,
     pub config: SurfaceConfiguration,
-    // pub depth_texture: Texture,
-    pub depth_texture: UniformBinding<DepthTexture>,
+    // the scene gets rendered here before post processing, it also owns the depth texture
+    pub scene_target: RenderTarget,
+    pub post_process_target: RenderTarget,
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
     pub screen_model: Model,
@@ -17,6 +18,28 @@ pub struct SurfaceContext<'a> {
     pub window_id: WindowId,
     pub size: (u32, u32),
     pub window: Arc<Window>,
+    pub frame_allocations: u64,
+    // the scene and post processing run in Rgba16Float and only get tonemapped when blitted to the surface
+    pub hdr: bool,
+    pub tonemap: UniformBinding<TonemapSettings>,
+}
+
+impl SurfaceContext<'_> {
+    // Reallocates the offscreen targets if the surface size, format or multisample count changed
+    pub fn update_targets(&mut self) {
+        let (width, height) = (self.config.width, self.config.height);
+        let sample_count = *MULTISAMPLE_COUNT.lock().unwrap();
+        let scene_format = self.scene_format();
+        update_targets(&self.device, &mut self.scene_target, &mut self.post_process_target, width, height, scene_format, sample_count);
+    }
+
+    // Only used with hdr, otherwise the blit just copies the texture
+    pub fn set_tonemap(&mut self, settings: TonemapSettings) {
+        let settings = if self.hdr { settings } else { TonemapSettings { exposure: 1.0, mode: TonemapSettings::NONE, ..settings } };
+        if settings != self.tonemap.value {
+            self.tonemap.set_data(&self.queue, settings);
+        }
+    }
 }
 
 impl SurfaceCtx for SurfaceContext<'_> {
@@ -29,7 +52,7 @@ impl SurfaceCtx for SurfaceContext<'_> {
     }
 
     fn depth_texture(&self) -> &UniformBinding<DepthTexture> {
-        &self.depth_texture
+        self.scene_target.depth.as_ref().unwrap()
     }
 
     fn device(&self) -> &Device {
@@ -67,7 +90,33 @@ impl SurfaceCtx for SurfaceContext<'_> {
     fn window(&self) -> &Window {
         &self.window
     }
-} 
+
+    fn scene_target(&self) -> &RenderTarget {
+        &self.scene_target
+    }
+
+    fn frame_allocations(&self) -> u64 {
+        self.frame_allocations
+    }
+
+    fn scene_format(&self) -> TextureFormat {
+        scene_format(self.hdr, self.config.format)
+    }
+}
+
+// the format of the scene and post processing textures for a surface
+pub fn scene_format(hdr: bool, surface_format: TextureFormat) -> TextureFormat {
+    if hdr { TextureFormat::Rgba16Float } else { surface_format }
+}
+
+// the targets only get reallocated when something about them changed, see `RenderTarget::resize`
+fn update_targets(device: &Device, scene_target: &mut RenderTarget, post_process_target: &mut RenderTarget, width: u32, height: u32, format: TextureFormat, sample_count: u32) {
+    scene_target.set_formats(device, vec![format]);
+    scene_target.set_sample_count(device, sample_count);
+    scene_target.resize(device, width, height);
+    post_process_target.set_formats(device, vec![format]);
+    post_process_target.resize(device, width, height);
+}
 
 pub trait SurfaceCtx {
     fn surface(&'_ self) -> &'_ wgpu::Surface<'_>;
@@ -82,4 +131,39 @@ pub trait SurfaceCtx {
     fn window_id(&self) -> &WindowId;
     fn size(&self) -> (u32, u32);
     fn window(&self) -> &Window;
-}
\ No newline at end of file
+    fn scene_target(&self) -> &RenderTarget;
+    // Number of textures created during the last frame, 0 once nothing is being resized
+    fn frame_allocations(&self) -> u64;
+    // The format of the scene and post processing textures, handler pipelines should target this instead of `config().format`
+    fn scene_format(&self) -> TextureFormat;
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::TextureFormat;
+
+    use crate::{render_target::{RenderTarget, RenderTargetConfig}, test_support, texture::texture_allocations};
+
+    use super::update_targets;
+
+    #[test]
+    fn steady_frames_allocate_no_textures() {
+        let Some((device, _queue)) = test_support::device() else { return; };
+        let _lock = test_support::GPU_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let format = TextureFormat::Rgba8Unorm;
+        let mut scene_target = RenderTarget::new(&device, "Scene", 64, 64, RenderTargetConfig { formats: vec![format], depth: true, ..Default::default() });
+        let mut post_process_target = RenderTarget::new(&device, "Post Processing", 64, 64, RenderTargetConfig { formats: vec![format], ..Default::default() });
+        update_targets(&device, &mut scene_target, &mut post_process_target, 64, 64, format, 1);
+        let before = texture_allocations();
+        for _ in 0..10 {
+            update_targets(&device, &mut scene_target, &mut post_process_target, 64, 64, format, 1);
+        }
+        assert_eq!(texture_allocations() - before, 0);
+        let before = texture_allocations();
+        update_targets(&device, &mut scene_target, &mut post_process_target, 128, 64, format, 1);
+        assert_ne!(texture_allocations() - before, 0);
+        let before = texture_allocations();
+        update_targets(&device, &mut scene_target, &mut post_process_target, 128, 64, format, 1);
+        assert_eq!(texture_allocations() - before, 0);
+    }
+}
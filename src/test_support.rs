@@ -0,0 +1,16 @@
+use std::sync::Mutex;
+
+use wgpu::{Device, Queue};
+
+// the texture allocation counter is global, tests that look at it or create textures hold this so they don't count each other's
+pub static GPU_LOCK: Mutex<()> = Mutex::new(());
+
+// A headless device, None when the machine has no adapter so the GPU tests are skipped instead of failing
+pub fn device() -> Option<(Device, Queue)> {
+    let instance = wgpu::Instance::default();
+    let Ok(adapter) = pollster::block_on(instance.request_adapter(&Default::default())) else {
+        eprintln!("no wgpu adapter, skipping GPU test");
+        return None;
+    };
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor { required_limits: adapter.limits(), ..Default::default() })).ok()
+}
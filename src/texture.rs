@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use image::GenericImageView;
 use anyhow::*;
 use wgpu::{BindGroupLayout, Device, TextureFormat, TextureUsages, TextureView, TextureViewDimension};
@@ -6,6 +8,54 @@ use crate::{binding::{Binding, Resource}, shader::ShaderType};
 
 const STORAGE_FORMATS: [TextureFormat; 4] = [TextureFormat::Rgba32Float, TextureFormat::Rgba16Float, TextureFormat::Rgba8Unorm, TextureFormat::R32Float];
 
+static TEXTURE_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+// Total number of GPU textures created through this module, used to check that frames don't allocate
+pub fn texture_allocations() -> u64 {
+    TEXTURE_ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn create_texture(device: &Device, desc: &wgpu::TextureDescriptor) -> wgpu::Texture {
+    TEXTURE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    device.create_texture(desc)
+}
+
+// The texels of a cubemap face in `format`, HDR images keep their range in the float formats
+fn face_texels(face: &image::DynamicImage, format: TextureFormat) -> Result<Vec<u8>> {
+    Ok(match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => face.to_rgba8().into_raw(),
+        TextureFormat::Rgba16Float => face.to_rgba32f().iter().flat_map(|value| f32_to_f16(*value).to_le_bytes()).collect(),
+        TextureFormat::Rgba32Float => bytemuck::cast_slice(&face.to_rgba32f()).to_vec(),
+        _ => bail!("cubemaps can only be made from images in Rgba8Unorm, Rgba8UnormSrgb, Rgba16Float or Rgba32Float, not {format:?}"),
+    })
+}
+
+// rounds to the nearest half, too large values become infinity
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // subnormal, the implicit leading bit becomes part of the mantissa
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | ((mantissa >> shift) + ((mantissa >> (shift - 1)) & 1)) as u16;
+    }
+    // a carry out of the mantissa correctly rounds up into the exponent
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1)) as u16
+}
+
 #[derive(Clone)]
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -63,7 +113,7 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(
+        let texture = create_texture(device,
             &wgpu::TextureDescriptor {
                 label,
                 size,
@@ -111,13 +161,19 @@ impl Texture {
         Ok(Self { texture, view, sampler, size, format: format.unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb), dimensions: TextureViewDimension::D2, sample_count: 1 })
     }
 
+    // 1x1 texture used in place of missing material maps
+    pub fn from_color(device: &wgpu::Device, queue: &wgpu::Queue, color: [u8; 4], format: wgpu::TextureFormat) -> Result<Self> {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image(device, queue, &img, Some("Color Texture"), Some(format), None, None, None)
+    }
+
     pub fn blank_texture(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32) -> Self {
         let size = wgpu::Extent3d {
             width,
             height,
             depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(
+        let texture = create_texture(device,
             &wgpu::TextureDescriptor {
                 label: Some("Temp Draw Texture"),
                 size,
@@ -125,7 +181,8 @@ impl Texture {
                 sample_count,
                 dimension: wgpu::TextureDimension::D2,
                 format,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT | if STORAGE_FORMATS.contains(&format) { TextureUsages::STORAGE_BINDING } else { TextureUsages::TEXTURE_BINDING },
+                // multisampled textures can't be storage textures
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT | if STORAGE_FORMATS.contains(&format) && sample_count == 1 { TextureUsages::STORAGE_BINDING } else { TextureUsages::TEXTURE_BINDING },
                 view_formats: &[format],
             }
         );
@@ -158,7 +215,7 @@ impl Texture {
             height,
             depth_or_array_layers: depth,
         };
-        let texture = device.create_texture(
+        let texture = create_texture(device,
             &wgpu::TextureDescriptor {
                 label: Some("Temp 3D Texture"),
                 size,
@@ -196,6 +253,86 @@ impl Texture {
         }
     }
     
+    // Faces are in the order +X, -X, +Y, -Y, +Z, -Z and have to be square and the same size
+    pub fn cubemap_from_images(device: &wgpu::Device, queue: &wgpu::Queue, faces: [&image::DynamicImage; 6], label: Option<&str>, format: Option<wgpu::TextureFormat>) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        if width != height || faces.iter().any(|face| face.dimensions() != (width, height)) {
+            bail!("cubemap faces have to be square and the same size");
+        }
+        let format = format.unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb);
+        let faces = faces.iter().map(|face| face_texels(face, format)).collect::<Result<Vec<_>>>()?;
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = create_texture(device,
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[format],
+            }
+        );
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                },
+                face,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(face.len() as u32 / height),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d { depth_or_array_layers: 1, ..size },
+            );
+        }
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+        Ok(Self { texture, view, sampler, size, format, dimensions: TextureViewDimension::Cube, sample_count: 1 })
+    }
+
+    pub fn with_sampler(self, device: &wgpu::Device, filter_mode: wgpu::FilterMode, address_mode: wgpu::AddressMode) -> Self {
+        self.with_address_modes(device, filter_mode, address_mode, address_mode)
+    }
+
+    // separate wrapping along u and v, like glTF samplers have
+    pub fn with_address_modes(mut self, device: &wgpu::Device, filter_mode: wgpu::FilterMode, address_mode_u: wgpu::AddressMode, address_mode_v: wgpu::AddressMode) -> Self {
+        self.sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u,
+                address_mode_v,
+                address_mode_w: address_mode_u,
+                mag_filter: filter_mode,
+                min_filter: filter_mode,
+                mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+        self
+    }
+
     pub fn normalized_dimensions(&self) -> (f32, f32) {
         let dist = ((self.texture.width() as f32).powf(2.0)+(self.texture.height() as f32).powf(2.0)).sqrt();
         (self.texture.width() as f32/dist, self.texture.height() as f32/dist)
@@ -226,7 +363,7 @@ impl Binding for Texture {
                 visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::VERTEX,
                 ty: wgpu::BindingType::Texture {
                     multisampled: config.sample_count > 1,
-                    view_dimension: wgpu::TextureViewDimension::D2,
+                    view_dimension: config.dimensions,
                     sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
                 count: None,
@@ -252,15 +389,21 @@ impl Binding for Texture {
     fn shader_type(config: TextureLayoutConfig) -> ShaderType {
         ShaderType {
             var_types: vec!["".into(), "".into()],
-            wgsl_types: vec![if config.dimensions == TextureViewDimension::D3 { "texture_3d<f32>".into() } else { "texture_2d<f32>".into() }, "sampler".into()],
+            wgsl_types: vec![match config.dimensions {
+                TextureViewDimension::D3 => "texture_3d<f32>".into(),
+                TextureViewDimension::Cube => "texture_cube<f32>".into(),
+                _ => "texture_2d<f32>".into(),
+            }, "sampler".into()],
         }
     }
 }
 
+#[derive(Clone)]
 pub struct DepthTexture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub sample_count: u32,
 }
 
 impl DepthTexture {
@@ -282,7 +425,7 @@ impl DepthTexture {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING,
         };
-        let texture = device.create_texture(&desc);
+        let texture = create_texture(device, &desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(
@@ -300,22 +443,23 @@ impl DepthTexture {
             texture, 
             view,
             sampler,
+            sample_count,
         }
     }
 }
 
 impl Binding for DepthTexture {
-    type LayoutConfig = ();
+    type LayoutConfig = TextureLayoutConfig;
     fn layout_config(&self) -> Self::LayoutConfig {
-        ()
+        TextureLayoutConfig { dimensions: TextureViewDimension::D2, sample_count: self.sample_count }
     }
-    fn layout(_config: (), _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
+    fn layout(config: TextureLayoutConfig, _ty: Option<wgpu::BindingType>) -> Vec<wgpu::BindGroupLayoutEntry> {
         vec![
             wgpu::BindGroupLayoutEntry {
                 binding: 0,
                 visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Texture {
-                    multisampled: false,
+                    multisampled: config.sample_count > 1,
                     view_dimension: wgpu::TextureViewDimension::D2,
                     sample_type: wgpu::TextureSampleType::Depth,
                 },
@@ -339,10 +483,10 @@ impl Binding for DepthTexture {
         ]
     }
 
-    fn shader_type(_config: ()) -> ShaderType {
+    fn shader_type(config: TextureLayoutConfig) -> ShaderType {
         ShaderType {
             var_types: vec!["".into(), "".into()],
-            wgsl_types: vec!["texture_depth_2d".into(), "sampler".into()],
+            wgsl_types: vec![if config.sample_count > 1 { "texture_depth_multisampled_2d".into() } else { "texture_depth_2d".into() }, "sampler".into()],
         }
     }
 }
@@ -407,4 +551,22 @@ impl Binding for StorageTexture {
             wgsl_types: vec![if config.dimensions == TextureViewDimension::D3 { format!("texture_storage_3d<{format_string}, read_write>") } else { format!("texture_storage_2d<{format_string}, read_write>") }],
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::f32_to_f16;
+
+    #[test]
+    fn converts_f32_to_f16() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        // the smallest subnormal half
+        assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001);
+        assert_eq!(f32_to_f16(1e-9), 0x0000);
+    }
+}
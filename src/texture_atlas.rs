@@ -0,0 +1,367 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail};
+use image::{DynamicImage, RgbaImage};
+use serde_json::{json, Value};
+use wgpu::{Device, Queue};
+
+use crate::{resource_loader::{load_resource, resource_paths}, texture::Texture};
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "tga", "gif"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRect {
+    pub page: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // min u, min v, max u, max v
+    pub uv: [f32; 4],
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasLayout {
+    pub page_width: u32,
+    pub page_height: u32,
+    pub padding: u32,
+    pub pages: Vec<String>,
+    pub rects: HashMap<String, AtlasRect>,
+}
+
+impl AtlasLayout {
+    pub fn get(&self, name: &str) -> Option<&AtlasRect> {
+        self.rects.get(name)
+    }
+
+    pub fn num_pages(&self) -> u32 {
+        self.rects.values().map(|rect| rect.page + 1).max().unwrap_or(0).max(self.pages.len() as u32)
+    }
+
+    pub fn to_json(&self) -> String {
+        let rects = self.rects.iter().map(|(name, rect)| {
+            (name.clone(), json!({
+                "page": rect.page,
+                "x": rect.x,
+                "y": rect.y,
+                "width": rect.width,
+                "height": rect.height,
+                "uv": rect.uv,
+            }))
+        }).collect::<serde_json::Map<String, Value>>();
+        serde_json::to_string_pretty(&json!({
+            "page_width": self.page_width,
+            "page_height": self.page_height,
+            "padding": self.padding,
+            "pages": self.pages,
+            "rects": rects,
+        })).unwrap()
+    }
+
+    pub fn from_json(source: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_str(source)?;
+        let pages = value.get("pages").and_then(|pages| pages.as_array()).map(|pages| {
+            pages.iter().filter_map(|page| page.as_str().map(|page| page.to_string())).collect()
+        }).unwrap_or_default();
+        let mut rects = HashMap::new();
+        for (name, rect) in value.get("rects").and_then(|rects| rects.as_object()).ok_or(anyhow!("atlas layout is missing \"rects\""))? {
+            let uv = rect.get("uv").and_then(|uv| uv.as_array()).ok_or(anyhow!("atlas rect {name} is missing \"uv\""))?;
+            if uv.len() != 4 {
+                bail!("atlas rect {name} has {} uv components, expected 4", uv.len());
+            }
+            rects.insert(name.clone(), AtlasRect {
+                page: json_u32(rect, "page")?,
+                x: json_u32(rect, "x")?,
+                y: json_u32(rect, "y")?,
+                width: json_u32(rect, "width")?,
+                height: json_u32(rect, "height")?,
+                uv: [0, 1, 2, 3].map(|i| uv[i].as_f64().unwrap_or(0.0) as f32),
+            });
+        }
+        Ok(Self {
+            page_width: json_u32(&value, "page_width")?,
+            page_height: json_u32(&value, "page_height")?,
+            padding: json_u32(&value, "padding")?,
+            pages,
+            rects,
+        })
+    }
+}
+
+fn json_u32(value: &Value, key: &str) -> anyhow::Result<u32> {
+    value.get(key).and_then(|it| it.as_u64()).map(|it| it as u32).ok_or(anyhow!("atlas layout is missing \"{key}\""))
+}
+
+pub struct TextureAtlasConfig {
+    pub page_width: u32,
+    pub page_height: u32,
+    pub padding: u32,
+    pub edge_bleed: bool,
+    pub filter_mode: wgpu::FilterMode,
+}
+
+impl Default for TextureAtlasConfig {
+    fn default() -> Self {
+        Self { page_width: 2048, page_height: 2048, padding: 2, edge_bleed: true, filter_mode: wgpu::FilterMode::Nearest }
+    }
+}
+
+pub struct TextureAtlasBuilder {
+    pub config: TextureAtlasConfig,
+    images: Vec<(String, RgbaImage)>,
+}
+
+impl TextureAtlasBuilder {
+    pub fn new(config: TextureAtlasConfig) -> Self {
+        Self { config, images: vec![] }
+    }
+
+    pub fn add_image(&mut self, name: &str, image: &DynamicImage) {
+        self.images.retain(|(existing, _)| existing != name);
+        self.images.push((name.into(), image.to_rgba8()));
+    }
+
+    pub fn add_resource(&mut self, path: &str) -> anyhow::Result<()> {
+        let bytes = load_resource(path).ok_or(anyhow!("tried to load resource at {path}"))?;
+        self.add_image(path, &image::load_from_memory(&bytes)?);
+        Ok(())
+    }
+
+    // Adds every image under `prefix`, named by its path relative to the prefix without the extension
+    pub fn add_resource_dir(&mut self, prefix: &str) -> anyhow::Result<()> {
+        for path in resource_paths(prefix) {
+            let relative = Path::new(&path[prefix.len()..]);
+            let Some(extension) = relative.extension().and_then(|it| it.to_str()) else { continue; };
+            if !IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+                continue;
+            }
+            let name = relative.with_extension("").to_str().unwrap().trim_start_matches("/").to_string();
+            let bytes = load_resource(&path).ok_or(anyhow!("tried to load resource at {path}"))?;
+            self.add_image(&name, &image::load_from_memory(&bytes)?);
+        }
+        Ok(())
+    }
+
+    pub fn pack(&self) -> anyhow::Result<(AtlasLayout, Vec<RgbaImage>)> {
+        let TextureAtlasConfig { page_width, page_height, padding, edge_bleed, .. } = self.config;
+        let mut order = (0..self.images.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            let (a, b) = (&self.images[*a].1, &self.images[*b].1);
+            b.height().cmp(&a.height()).then(b.width().cmp(&a.width()))
+        });
+        let mut packers: Vec<ShelfPacker> = vec![];
+        let mut pages: Vec<RgbaImage> = vec![];
+        let mut rects = HashMap::new();
+        for i in order {
+            let (name, image) = &self.images[i];
+            if image.width() == 0 || image.height() == 0 {
+                bail!("image {name} is empty");
+            }
+            let padded_width = image.width() + padding * 2;
+            let padded_height = image.height() + padding * 2;
+            if padded_width > page_width || padded_height > page_height {
+                bail!("image {name} ({}x{}) does not fit in a {page_width}x{page_height} atlas page", image.width(), image.height());
+            }
+            let placed = packers.iter_mut().enumerate().find_map(|(page, packer)| {
+                packer.place(padded_width, padded_height, page_width, page_height).map(|pos| (page, pos))
+            });
+            let (page, (x, y)) = match placed {
+                Some(placed) => placed,
+                None => {
+                    let mut packer = ShelfPacker::default();
+                    let pos = packer.place(padded_width, padded_height, page_width, page_height).unwrap();
+                    packers.push(packer);
+                    pages.push(RgbaImage::new(page_width, page_height));
+                    (pages.len() - 1, pos)
+                }
+            };
+            blit(&mut pages[page], image, x, y, padding, edge_bleed);
+            let (x, y) = (x + padding, y + padding);
+            rects.insert(name.clone(), AtlasRect {
+                page: page as u32,
+                x,
+                y,
+                width: image.width(),
+                height: image.height(),
+                uv: [
+                    x as f32 / page_width as f32,
+                    y as f32 / page_height as f32,
+                    (x + image.width()) as f32 / page_width as f32,
+                    (y + image.height()) as f32 / page_height as f32,
+                ],
+            });
+        }
+        let layout = AtlasLayout { page_width, page_height, padding, pages: vec![], rects };
+        Ok((layout, pages))
+    }
+
+    pub fn build(&self, device: &Device, queue: &Queue) -> anyhow::Result<TextureAtlas> {
+        let (layout, pages) = self.pack()?;
+        TextureAtlas::from_pages(layout, pages, self.config.filter_mode, device, queue)
+    }
+
+    // Writes `{name}.json` and `{name}_{page}.png` into `dir`, meant to be called from a build script before `generate_resources`
+    pub fn bake(&self, dir: &Path, name: &str) -> anyhow::Result<AtlasLayout> {
+        let (mut layout, pages) = self.pack()?;
+        std::fs::create_dir_all(dir)?;
+        for (i, page) in pages.iter().enumerate() {
+            let file_name = format!("{name}_{i}.png");
+            page.save(dir.join(&file_name))?;
+            layout.pages.push(file_name);
+        }
+        std::fs::write(dir.join(format!("{name}.json")), layout.to_json())?;
+        Ok(layout)
+    }
+}
+
+#[derive(Default)]
+struct ShelfPacker {
+    shelves: Vec<Shelf>,
+    next_y: u32,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32,
+}
+
+impl ShelfPacker {
+    fn place(&mut self, width: u32, height: u32, page_width: u32, page_height: u32) -> Option<(u32, u32)> {
+        for shelf in &mut self.shelves {
+            if height <= shelf.height && shelf.next_x + width <= page_width {
+                let pos = (shelf.next_x, shelf.y);
+                shelf.next_x += width;
+                return Some(pos);
+            }
+        }
+        if self.next_y + height > page_height || width > page_width {
+            return None;
+        }
+        let pos = (0, self.next_y);
+        self.shelves.push(Shelf { y: self.next_y, height, next_x: width });
+        self.next_y += height;
+        Some(pos)
+    }
+}
+
+// Copies `image` into `page` at (x + padding, y + padding), extruding its edge pixels into the padding when `edge_bleed` is set
+fn blit(page: &mut RgbaImage, image: &RgbaImage, x: u32, y: u32, padding: u32, edge_bleed: bool) {
+    let (width, height) = image.dimensions();
+    for py in 0..height + padding * 2 {
+        for px in 0..width + padding * 2 {
+            let inside = px >= padding && py >= padding && px < width + padding && py < height + padding;
+            if !inside && !edge_bleed {
+                continue;
+            }
+            let src_x = px.saturating_sub(padding).min(width - 1);
+            let src_y = py.saturating_sub(padding).min(height - 1);
+            page.put_pixel(x + px, y + py, *image.get_pixel(src_x, src_y));
+        }
+    }
+}
+
+pub struct TextureAtlas {
+    pub layout: AtlasLayout,
+    pub pages: Vec<Texture>,
+}
+
+impl TextureAtlas {
+    pub fn from_pages(layout: AtlasLayout, pages: Vec<RgbaImage>, filter_mode: wgpu::FilterMode, device: &Device, queue: &Queue) -> anyhow::Result<Self> {
+        let pages = pages.into_iter().enumerate().map(|(i, page)| {
+            Texture::from_image(device, queue, &DynamicImage::ImageRgba8(page), Some(&format!("Atlas Page {i}")), None, None, Some(filter_mode), Some(wgpu::AddressMode::ClampToEdge))
+        }).collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { layout, pages })
+    }
+
+    // Loads a layout written by `TextureAtlasBuilder::bake`, the pages are resolved relative to the layout file
+    pub fn load(layout_path: &str, filter_mode: wgpu::FilterMode, device: &Device, queue: &Queue) -> anyhow::Result<Self> {
+        let layout_bytes = load_resource(layout_path).ok_or(anyhow!("tried to load resource at {layout_path}"))?;
+        let layout = AtlasLayout::from_json(&String::from_utf8(layout_bytes)?)?;
+        let dir = Path::new(layout_path).parent().unwrap_or(Path::new(""));
+        let pages = layout.pages.iter().map(|page| {
+            let path = dir.join(page);
+            let path = path.to_str().unwrap();
+            let bytes = load_resource(path).ok_or(anyhow!("tried to load resource at {path}"))?;
+            Ok(image::load_from_memory(&bytes)?.to_rgba8())
+        }).collect::<anyhow::Result<Vec<_>>>()?;
+        Self::from_pages(layout, pages, filter_mode, device, queue)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AtlasRect> {
+        self.layout.get(name)
+    }
+
+    pub fn uv(&self, name: &str) -> Option<[f32; 4]> {
+        self.layout.get(name).map(|rect| rect.uv)
+    }
+
+    pub fn page_for(&self, name: &str) -> Option<&Texture> {
+        self.layout.get(name).and_then(|rect| self.pages.get(rect.page as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    use super::{TextureAtlasBuilder, TextureAtlasConfig};
+
+    fn builder(page_size: u32, padding: u32) -> TextureAtlasBuilder {
+        TextureAtlasBuilder::new(TextureAtlasConfig { page_width: page_size, page_height: page_size, padding, ..Default::default() })
+    }
+
+    fn solid(width: u32, height: u32, value: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([value, 0, 0, 255])))
+    }
+
+    #[test]
+    fn packed_rects_stay_apart_and_inside_the_pages() {
+        let (page_size, padding) = (128, 2);
+        let mut builder = builder(page_size, padding);
+        // more than a page worth of mixed sizes so some go onto later pages
+        let mut seed = 7u32;
+        for i in 0..60 {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            builder.add_image(&format!("{i}"), &solid(4 + (seed >> 8) % 28, 4 + (seed >> 20) % 28, i as u8 + 1));
+        }
+        let (layout, pages) = builder.pack().unwrap();
+        assert!(pages.len() > 1);
+        assert_eq!(layout.rects.len(), 60);
+        let rects = layout.rects.iter().collect::<Vec<_>>();
+        for (i, (name, rect)) in rects.iter().enumerate() {
+            assert!((rect.page as usize) < pages.len());
+            assert!(rect.x >= padding && rect.y >= padding);
+            assert!(rect.x + rect.width + padding <= page_size && rect.y + rect.height + padding <= page_size);
+            // the image itself ends up where the rect says
+            let value = name.parse::<u8>().unwrap() + 1;
+            assert_eq!(pages[rect.page as usize].get_pixel(rect.x, rect.y)[0], value);
+            assert_eq!(pages[rect.page as usize].get_pixel(rect.x + rect.width - 1, rect.y + rect.height - 1)[0], value);
+            for (_, other) in &rects[i + 1..] {
+                // the padding around both has to stay apart too
+                let apart = rect.page != other.page
+                    || rect.x + rect.width + padding * 2 <= other.x
+                    || other.x + other.width + padding * 2 <= rect.x
+                    || rect.y + rect.height + padding * 2 <= other.y
+                    || other.y + other.height + padding * 2 <= rect.y;
+                assert!(apart, "{rect:?} overlaps {other:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn packing_fails_when_an_image_does_not_fit() {
+        let mut fits = builder(64, 2);
+        fits.add_image("full", &solid(60, 60, 1));
+        let (layout, pages) = fits.pack().unwrap();
+        assert_eq!((pages.len(), layout.rects["full"].x, layout.rects["full"].y), (1, 2, 2));
+
+        // the padding has to fit on the page as well
+        for (width, height) in [(61, 60), (60, 61), (100, 4)] {
+            let mut too_big = builder(64, 2);
+            too_big.add_image("small", &solid(4, 4, 1));
+            too_big.add_image("big", &solid(width, height, 2));
+            assert!(too_big.pack().is_err());
+        }
+    }
+}
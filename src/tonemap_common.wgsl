@@ -0,0 +1,32 @@
+// Shared by the tonemap effect and the screen renderer, which tonemaps HDR scenes when blitting them to the surface
+
+struct TonemapSettings {
+    exposure: f32,
+    // 0 = ACES, 1 = Reinhard, 2 = none (only applies exposure)
+    mode: u32,
+    white_point: f32,
+    padding: f32,
+};
+
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3(0.0), vec3(1.0));
+}
+
+fn reinhard(x: vec3<f32>, white_point: f32) -> vec3<f32> {
+    return x * (1.0 + x / (white_point * white_point)) / (1.0 + x);
+}
+
+fn apply_tonemap(color: vec3<f32>, settings: TonemapSettings) -> vec3<f32> {
+    let exposed = color * settings.exposure;
+    if settings.mode == 0u {
+        return aces(exposed);
+    } else if settings.mode == 1u {
+        return reinhard(exposed, settings.white_point);
+    }
+    return exposed;
+}
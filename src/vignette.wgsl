@@ -0,0 +1,40 @@
+struct VignetteSettings {
+    color: vec3<f32>,
+    intensity: f32,
+    radius: f32,
+    softness: f32,
+    padding1: f32,
+    padding2: f32,
+};
+
+previous_texture: $0,0;
+previous_sampler: $0,1;
+settings: $3;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(previous_texture, previous_sampler, in.tex_coords);
+    let dist = distance(in.tex_coords, vec2(0.5));
+    let vignette = smoothstep(settings.radius - settings.softness, settings.radius, dist) * settings.intensity;
+    return vec4(mix(color.rgb, settings.color, vignette), color.a);
+}
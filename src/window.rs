@@ -12,13 +12,18 @@ use winit::keyboard::{KeyCode, ModifiersKeyState, PhysicalKey};
 use winit::window::{Window, WindowId};
 use winit::event_loop::ActiveEventLoop;
 
-use crate::binding::{Binding, Descriptor, UniformBinding, bind_resources, create_layout};
+use crate::binding::{Binding, Descriptor, UniformBinding, create_layout};
 use crate::culling::AABB;
+use crate::deferred::DeferredRenderer;
+use crate::oit::OitRenderer;
 use crate::model::{Model, Render, ToRaw};
+use crate::post_process::PostProcessChain;
+use crate::post_process_effects::TonemapSettings;
+use crate::render_target::{RenderTarget, RenderTargetConfig};
 use crate::resource_loader::{ResourceType, GLOBAL_PROJECT_RESOURCES};
 use crate::shader::{Shader, ShaderConfig, CUSTOM_SHADER_TYPE_SOURCE};
-use crate::surface_context::{SurfaceContext, SurfaceCtx};
-use crate::texture::{DepthTexture, Texture, TextureLayoutConfig};
+use crate::surface_context::{scene_format, SurfaceContext, SurfaceCtx};
+use crate::texture::{texture_allocations, Texture, TextureLayoutConfig};
 
 pub static MULTISAMPLE_COUNT: Mutex<u32> = Mutex::new(1);
 
@@ -84,9 +89,22 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                 config.format = format;
             }
             surface.configure(&device, &config);
-            let depth_texture = DepthTexture::create_depth_texture(&device, config.width, config.height, "Depth Texture", MULTISAMPLE_COUNT.lock().unwrap().clone());
-            let depth_texture_binding = UniformBinding::new(&device, "Depth Texture", depth_texture, None);
-            let texture_renderer_shader = Shader::new("buildins/screen_renderer.wgsl", &device, vec![config.format], vec![&create_layout::<Texture>(TextureLayoutConfig {dimensions: D2, sample_count: 1 },  &device)], vec![&Texture::shader_type(TextureLayoutConfig {dimensions: D2, sample_count: 1 })], vec![BasicVertex::desc()], ShaderConfig::default());
+            let scene_format = scene_format(surface_config.hdr, config.format);
+            let scene_target = RenderTarget::new(&device, "Temp Texture", config.width, config.height, RenderTargetConfig {
+                formats: vec![scene_format],
+                depth: true,
+                sample_count: *MULTISAMPLE_COUNT.lock().unwrap(),
+                filter_mode: wgpu::FilterMode::Linear,
+                ..Default::default()
+            });
+            let post_process_target = RenderTarget::new(&device, "Post Processing Texture", config.width, config.height, RenderTargetConfig {
+                formats: vec![scene_format],
+                depth: false,
+                clear_colors: vec![wgpu::Color::BLACK],
+                ..Default::default()
+            });
+            let tonemap = UniformBinding::new(&device, "Tonemap Settings", TonemapSettings { exposure: 1.0, mode: TonemapSettings::NONE, ..Default::default() }, None);
+            let texture_renderer_shader = Shader::new("buildins/screen_renderer.wgsl", &device, vec![config.format], vec![&create_layout::<Texture>(TextureLayoutConfig {dimensions: D2, sample_count: 1 },  &device), &tonemap.layout], vec![&Texture::shader_type(TextureLayoutConfig {dimensions: D2, sample_count: 1 }), &tonemap.shader_type], vec![BasicVertex::desc()], ShaderConfig { enable_depth_texture: false, multisample_count: 1, ..Default::default() });
             let screen_model = BasicVertex::one_face(&device);
             let surface_context = SurfaceContext {
                 window_id: window.id(),
@@ -95,10 +113,14 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                 size: size.into(),
                 config,
                 texture_renderer_shader,
-                depth_texture: depth_texture_binding,
+                scene_target,
+                post_process_target,
                 device: Arc::new(device),
                 queue: Arc::new(queue),
                 screen_model,
+                frame_allocations: 0,
+                hdr: surface_config.hdr,
+                tonemap,
             };
             self.surface_context = Some(surface_context);
             self.handler = Some((self.ready)(self.surface_context.as_ref().unwrap()));
@@ -189,8 +211,7 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                         handler.resize(surface_context, Vector2::new(surface_context.config.width, surface_context.config.height));
                     }
                     surface_context.surface.configure(&surface_context.device, &surface_context.config);
-                    let depth_texture = DepthTexture::create_depth_texture(&surface_context.device, surface_context.config.width, surface_context.config.height, "Depth Texture", MULTISAMPLE_COUNT.lock().unwrap().clone());
-                    surface_context.depth_texture.replace_data(&surface_context.device, depth_texture);
+                    surface_context.update_targets();
                 }
             }
             WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
@@ -200,25 +221,22 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                     if let Some(handler) = &mut self.handler {
                         handler.resize(surface_context, Vector2::new(surface_context.config.width, surface_context.config.height));
                     }
-                    let depth_texture = DepthTexture::create_depth_texture(&surface_context.device, surface_context.config.width, surface_context.config.height, "Depth Texture", MULTISAMPLE_COUNT.lock().unwrap().clone());
-                    surface_context.depth_texture.replace_data(&surface_context.device, depth_texture);
+                    surface_context.update_targets();
                     surface_context.surface.configure(&surface_context.device, &surface_context.config);
             }
             }
             WindowEvent::RedrawRequested if self.surface_context.as_ref().map(|ctx| ctx.window_id) == Some(window_id) => {
-                if let Some(surface_context) = &self.surface_context {
+                if let Some(surface_context) = &mut self.surface_context {
+                    let allocations_before = texture_allocations();
                     let delta = SystemTime::now().duration_since(self.last_time).unwrap_or(Duration::from_millis(0));
                     self.last_time = SystemTime::now();
                     if let Some(handler) = &mut self.handler {
                         handler.update(surface_context, delta);
                     }
-                    let multisample_texture = if MULTISAMPLE_COUNT.lock().unwrap().clone() > 1 {
-                        Some(Texture::blank_texture(surface_context.device(), surface_context.config.width, surface_context.config.height, surface_context.config.format, MULTISAMPLE_COUNT.lock().unwrap().clone()))
-                    } else {
-                        None
-                    };
-                    let temp_texture = Texture::blank_texture(&surface_context.device, surface_context.config.width, surface_context.config.height, surface_context.config.format, 1);
-                    let temp_texture_binding = UniformBinding::new(&surface_context.device, "Temp Texture", temp_texture, None);
+                    let window_config = self.handler.as_ref().map(|handler| handler.config()).unwrap_or_default();
+                    surface_context.update_targets();
+                    surface_context.set_tonemap(window_config.tonemap);
+                    surface_context.scene_target.clear_colors = vec![window_config.background_color];
                     let output_result = surface_context.surface.get_current_texture();
                     let output = match output_result {
                         wgpu::CurrentSurfaceTexture::Success(texture) => texture,
@@ -239,73 +257,63 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                             label: Some("Render Encoder"),
                         });
-                    //render the game to a temporary texture
+                    let surface_context = &*surface_context;
+                    //with deferred rendering the opaque geometry goes into the G-buffer and gets lit into the scene texture first
+                    let gbuffer_pass = self.handler.as_mut().and_then(|handler| handler.deferred_renderer()).map(|deferred| {
+                        deferred.prepare(surface_context);
+                        deferred.gbuffer_pass()
+                    });
+                    if let Some(gbuffer_pass) = &gbuffer_pass {
+                        {
+                            let mut render_pass = gbuffer_pass.begin(&mut encoder);
+                            if let Some(handler) = &mut self.handler {
+                                handler.render_gbuffer(surface_context, &mut render_pass);
+                            }
+                        }
+                        if let Some(deferred) = self.handler.as_mut().and_then(|handler| handler.deferred_renderer()) {
+                            deferred.compose(surface_context, &mut encoder);
+                        }
+                    }
+                    //render the game to the cached scene texture
                     {
-                        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: Some("Temp Render Pass"),
-                            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                resolve_target: multisample_texture.as_ref().map(|_| &temp_texture_binding.value.view),
-                                view: multisample_texture.as_ref().map(|it| &it.view).unwrap_or(&temp_texture_binding.value.view),
-                                depth_slice: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(self.handler.as_ref().map(|handler| handler.config()).unwrap_or_default().background_color),
-                                    store: wgpu::StoreOp::Store,
-                                },
-                            })],
-                            multiview_mask: None,
-                            timestamp_writes: None,
-                            occlusion_query_set: None,
-                            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                                view: &surface_context.depth_texture.value.view,
-                                depth_ops: Some(wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(1.0),
-                                    store: wgpu::StoreOp::Store,
-                                }),
-                                stencil_ops: None,
-                            }),
-                        });
+                        let mut render_pass = surface_context.scene_target.begin_render_pass(&mut encoder, gbuffer_pass.is_none());
                         if let Some(handler) = &mut self.handler {
                             handler.render(surface_context, &mut render_pass);
                         }
                     }
+                    //weighted blended transparency goes into its own targets against the scene depth, then onto the scene texture
+                    let oit_pass = self.handler.as_mut().and_then(|handler| handler.oit_renderer()).map(|oit| {
+                        oit.prepare(surface_context);
+                        oit.pass(surface_context)
+                    });
+                    if let Some(oit_pass) = &oit_pass {
+                        {
+                            let mut render_pass = oit_pass.begin(&mut encoder);
+                            if let Some(handler) = &mut self.handler {
+                                handler.render_oit(surface_context, &mut render_pass);
+                            }
+                        }
+                        if let Some(oit) = self.handler.as_mut().and_then(|handler| handler.oit_renderer()) {
+                            oit.composite(surface_context, &mut encoder);
+                        }
+                    }
                     
-                    //create another temporary texture and use it to render post processing effects
-                    let post_process_texture = if self.handler.as_ref().map(|handler| handler.config()).unwrap_or_default().enable_post_processing {
-                        let post_process_texture = Texture::blank_texture(&surface_context.device, surface_context.config.width, surface_context.config.height, surface_context.config.format, 1);
+                    //use the cached post processing texture to render post processing effects
+                    let post_process_texture = if window_config.enable_post_processing {
                         {
-                            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                                label: Some("Post Processing Render Pass"),
-                                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                    view: &post_process_texture.view,
-                                    resolve_target: None,
-                                    depth_slice: None,
-                                    ops: wgpu::Operations {
-                                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                        store: wgpu::StoreOp::Store,
-                                    },
-                                })],
-                                multiview_mask: None,
-                                timestamp_writes: None,
-                                occlusion_query_set: None,
-                                // depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                                //     view: &surface_context.depth_texture.view,
-                                //     depth_ops: Some(wgpu::Operations {
-                                //         load: wgpu::LoadOp::Clear(1.0),
-                                //         store: wgpu::StoreOp::Store,
-                                //     }),
-                                //     stencil_ops: None,
-                                // }),
-                                depth_stencil_attachment: None,
-                            });
+                            let mut render_pass = surface_context.post_process_target.begin_render_pass(&mut encoder, true);
                             if let Some(handler) = &mut self.handler {
-                                handler.post_process_render(surface_context, &mut render_pass, &temp_texture_binding);
+                                handler.post_process_render(surface_context, &mut render_pass, &surface_context.scene_target.colors[0]);
                             }
                         }
-                        post_process_texture
+                        &surface_context.post_process_target.colors[0]
                     } else {
-                        temp_texture_binding.value
+                        &surface_context.scene_target.colors[0]
+                    };
+                    let post_process_texture = match self.handler.as_mut().and_then(|handler| handler.post_process_chain()) {
+                        Some(chain) => chain.run(surface_context, &mut encoder, &surface_context.scene_target.colors[0], post_process_texture),
+                        None => post_process_texture,
                     };
-                    let post_process_texture_binding = bind_resources(&post_process_texture, &surface_context.device);
                     //render that texture onto the screen
                     {
                         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -322,23 +330,20 @@ impl<'b: 'a, 'a, H: WindowHandler> ApplicationHandler for Surface<'b, 'a, H> {
                             multiview_mask: None,
                             timestamp_writes: None,
                             occlusion_query_set: None,
-                            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                                view: &surface_context.depth_texture.value.view,
-                                depth_ops: Some(wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(1.0),
-                                    store: wgpu::StoreOp::Store,
-                                }),
-                                stencil_ops: None,
-                            }),
+                            depth_stencil_attachment: None,
                         });
                         surface_context.texture_renderer_shader.bind(&mut render_pass);
-                        render_pass.set_bind_group(0, &post_process_texture_binding, &[]);
+                        render_pass.set_bind_group(0, &post_process_texture.binding, &[]);
+                        render_pass.set_bind_group(1, &surface_context.tonemap.binding, &[]);
 
                         surface_context.screen_model.render(&mut render_pass);
                     }
                     surface_context.queue.submit([encoder.finish()]);
 
                     surface_context.queue.present(output);
+                    if let Some(surface_context) = &mut self.surface_context {
+                        surface_context.frame_allocations = texture_allocations() - allocations_before;
+                    }
                 }
             }
             _ => {}
@@ -371,6 +376,19 @@ pub trait WindowHandler {
     fn input_event(&mut self, surface_context: &dyn SurfaceCtx, input_event: &KeyEvent, current_modifiers: &Modifiers);
     fn touch(&mut self, surface_context: &dyn SurfaceCtx, touch: &Touch);
     fn post_process_render<'a: 'b, 'c: 'b, 'b>(&'a mut self, surface_context: &'c dyn SurfaceCtx, render_pass: & mut RenderPass<'b>, surface_texture: &'c UniformBinding<Texture>);
+    fn post_process_chain(&mut self) -> Option<&mut PostProcessChain> {
+        None
+    }
+    // Turns on deferred rendering, `render_gbuffer` then draws the opaque geometry and `render` only what's forward shaded
+    fn deferred_renderer(&mut self) -> Option<&mut DeferredRenderer> {
+        None
+    }
+    fn render_gbuffer<'a: 'b, 'b>(&'a mut self, _surface_context: &'a dyn SurfaceCtx, _render_pass: &mut RenderPass<'b>) {}
+    // Turns on order independent transparency, `render_oit` draws into it with shaders using `OitRenderer::shader_config`
+    fn oit_renderer(&mut self) -> Option<&mut OitRenderer> {
+        None
+    }
+    fn render_oit<'a: 'b, 'b>(&'a mut self, _surface_context: &'a dyn SurfaceCtx, _render_pass: &mut RenderPass<'b>) {}
     fn other_window_event(&mut self, surface_context: &dyn SurfaceCtx, event: &WindowEvent);
     fn custom_shader_type_source() -> String;
     fn resources() -> Option<&'static phf::Map<&'static str, ResourceType>>;
@@ -380,6 +398,8 @@ pub struct WindowConfig {
     pub background_color: wgpu::Color,
     pub enable_post_processing: bool,
     pub multisample_count: u32,
+    // applied when blitting to the surface, ignored unless `SurfaceConfig::hdr` is set
+    pub tonemap: TonemapSettings,
 }
 
 impl Default for WindowConfig {
@@ -393,6 +413,7 @@ impl Default for WindowConfig {
             }, 
             enable_post_processing: false,
             multisample_count: 1,
+            tonemap: TonemapSettings::default(),
         }
     }
 }
@@ -400,11 +421,13 @@ impl Default for WindowConfig {
 pub struct SurfaceConfig {
     pub override_format: Option<wgpu::TextureFormat>,
     pub multisample_count: u32,
+    // render the scene into Rgba16Float so values above 1.0 survive post processing
+    pub hdr: bool,
 }
 
 impl Default for SurfaceConfig {
     fn default() -> Self {
-        Self { override_format: None, multisample_count: 4 }
+        Self { override_format: None, multisample_count: 4, hdr: false }
     }
 }
 